@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    num::NonZeroUsize,
+};
+
+use anyhow::Result;
+use camino::Utf8Path;
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+use crate::{
+    counter::{self, CountOptions, Counts},
+    parallel,
+};
+
+/// Compression formats recognized by the magic bytes at the start of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+const MAGIC_BYTES: [(Compression, &[u8]); 3] = [
+    (Compression::Gzip, &[0x1f, 0x8b]),
+    (Compression::Zstd, &[0x28, 0xb5, 0x2f, 0xfd]),
+    (Compression::Xz, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+];
+
+/// Length of the longest magic bytes, enough to detect any format.
+const HEADER_SIZE: usize = 6;
+
+impl Compression {
+    fn detect(header: &[u8]) -> Option<Self> {
+        MAGIC_BYTES
+            .iter()
+            .find(|(_, magic)| header.starts_with(magic))
+            .map(|&(compression, _)| compression)
+    }
+}
+
+/// Counts the number of bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    bytes: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.bytes += read;
+        Ok(read)
+    }
+}
+
+/// Counts objects in the decompressed content of a compressed file,
+/// other files are counted as is, in parallel if requested.
+pub(crate) fn count_file(
+    path: &Utf8Path,
+    threads: NonZeroUsize,
+    options: &CountOptions,
+) -> Result<Counts> {
+    let mut reader = BufReader::new(File::open(path)?);
+    // the header is only peeked at, so both counters read the file from the start
+    if Compression::detect(reader.fill_buf()?).is_none() {
+        let mut counts = parallel::count_buffered_file(reader, threads, options)?;
+        counts.compressed_bytes = counts.bytes;
+        return Ok(counts);
+    }
+
+    Ok(count_reader(reader, options)?)
+}
+
+/// Counts objects in the decompressed stream, without temporary files.
+///
+/// The input is counted as is if it's not compressed.
+/// Concatenated gzip members, zstd frames and xz streams are all decompressed.
+pub(crate) fn count_reader(reader: impl Read, options: &CountOptions) -> io::Result<Counts> {
+    let mut source = CountingReader {
+        inner: reader,
+        bytes: 0,
+    };
+    let header = read_header(&mut source)?;
+    let compression = Compression::detect(&header);
+
+    let input = header.as_slice().chain(&mut source);
+    let mut counts = match compression {
+        None => counter::count_reader(input, options)?,
+        Some(Compression::Gzip) => counter::count_reader(MultiGzDecoder::new(input), options)?,
+        Some(Compression::Zstd) => counter::count_reader(zstd::Decoder::new(input)?, options)?,
+        Some(Compression::Xz) => {
+            counter::count_reader(XzDecoder::new_multi_decoder(input), options)?
+        }
+    };
+
+    counts.compressed_bytes = source.bytes;
+
+    Ok(counts)
+}
+
+/// Reads the first bytes, fewer only if the input is shorter.
+fn read_header(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    reader.take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(content).expect("writing to vector");
+        encoder.finish().expect("writing to vector")
+    }
+
+    fn xz(content: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 1);
+        encoder.write_all(content).expect("writing to vector");
+        encoder.finish().expect("writing to vector")
+    }
+
+    fn zstd(content: &[u8]) -> Vec<u8> {
+        zstd::encode_all(content, 1).expect("reading from slice")
+    }
+
+    #[test]
+    fn test_decompressed_counts() {
+        let content = "привет мир\nhello world\n".repeat(100);
+        let options = CountOptions::default();
+        let expected = counter::count_reader(content.as_bytes(), &options).expect("reading slice");
+
+        for compress in [gzip, xz, zstd] {
+            let compressed = compress(content.as_bytes());
+            let counts = count_reader(compressed.as_slice(), &options).expect("valid stream");
+
+            assert_eq!(
+                Counts {
+                    compressed_bytes: 0,
+                    ..counts
+                },
+                expected
+            );
+            assert_eq!(counts.compressed_bytes, compressed.len());
+            assert!(counts.compressed_bytes < counts.bytes);
+        }
+    }
+
+    #[test]
+    fn test_concatenated_streams() {
+        for compress in [gzip, xz, zstd] {
+            let compressed = [compress(b"first\n"), compress(b"second\n")].concat();
+            let counts = count_reader(compressed.as_slice(), &CountOptions::default())
+                .expect("valid stream");
+
+            assert_eq!((counts.lines, counts.bytes), (2, 13));
+        }
+    }
+
+    #[test]
+    fn test_uncompressed() {
+        for content in [&b""[..], b"\x1f", b"plain text\n"] {
+            let counts = count_reader(content, &CountOptions::default()).expect("reading slice");
+            assert_eq!(counts.bytes, content.len());
+            assert_eq!(counts.compressed_bytes, content.len());
+        }
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut compressed = gzip(b"some text that is long enough to be compressed\n");
+        compressed.truncate(compressed.len() / 2);
+
+        let result = count_reader(compressed.as_slice(), &CountOptions::default());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,719 @@
+use std::{
+    fmt,
+    io::{self, Read},
+};
+
+use serde::Serialize;
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    records::{RecordCounter, RecordSeparator},
+    segment::LineSegmenter,
+};
+
+/// Width of the tab stops used when measuring the display width of a line.
+const TAB_WIDTH: usize = 8;
+
+/// Size of the buffer the input is read with, the memory usage doesn't depend on the input size.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// The kind of object that can be counted in an input.
+///
+/// The order of the variants is the order of the columns in the report,
+/// the same as in GNU `wc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Metric {
+    Lines,
+    Words,
+    UnicodeWords,
+    Chars,
+    Graphemes,
+    Bytes,
+    CompressedBytes,
+    MaxLineLength,
+    Records,
+    WordsPerRecord,
+    CharsPerRecord,
+}
+
+impl Metric {
+    /// Name of the metric in machine-readable reports.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Metric::Lines => "lines",
+            Metric::Words => "words",
+            Metric::UnicodeWords => "unicode_words",
+            Metric::Chars => "chars",
+            Metric::Graphemes => "graphemes",
+            Metric::Bytes => "bytes",
+            Metric::CompressedBytes => "compressed_bytes",
+            Metric::MaxLineLength => "max_line_length",
+            Metric::Records => "records",
+            Metric::WordsPerRecord => "words_per_record",
+            Metric::CharsPerRecord => "chars_per_record",
+        }
+    }
+}
+
+/// How to count bytes that are not valid UTF-8.
+///
+/// Invalid bytes are always counted as bytes, the policy only affects characters,
+/// words and line length.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum InvalidUtf8 {
+    /// Every invalid sequence is one `U+FFFD` character, as `String::from_utf8_lossy` does.
+    #[default]
+    Replace,
+    /// Every invalid byte is one character.
+    Bytes,
+    /// Invalid bytes are ignored, they neither form nor split words.
+    Skip,
+}
+
+/// Options that change how the input is counted.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CountOptions {
+    pub(crate) invalid_utf8: InvalidUtf8,
+    /// Count Unicode words and grapheme clusters, this requires keeping the current line in memory.
+    pub(crate) segment: bool,
+    /// Count records with the separator, besides lines.
+    pub(crate) records: Option<RecordSeparator>,
+    /// Keep the counted lines for [`Counter::take_lines`], so they are split exactly like `-l` counts them.
+    pub(crate) keep_lines: bool,
+}
+
+/// All the numbers counted in a single input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Counts {
+    pub(crate) lines: usize,
+    /// Sequences of non-whitespace characters.
+    pub(crate) words: usize,
+    /// Words by Unicode word boundaries, counted only with [`CountOptions::segment`].
+    pub(crate) unicode_words: usize,
+    pub(crate) chars: usize,
+    /// Extended grapheme clusters, counted only with [`CountOptions::segment`].
+    pub(crate) graphemes: usize,
+    /// Bytes of the decompressed content for compressed inputs.
+    pub(crate) bytes: usize,
+    /// Bytes of the input as read, counted only for decompressed inputs.
+    pub(crate) compressed_bytes: usize,
+    pub(crate) max_line_length: usize,
+    /// Records and the words and characters inside them, counted only with [`CountOptions::records`].
+    pub(crate) records: usize,
+    pub(crate) record_words: usize,
+    pub(crate) record_chars: usize,
+}
+
+/// Value of a metric in the report.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Value {
+    Count(usize),
+    /// An average per record, zero when there are no records.
+    Average(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Count(count) => fmt::Display::fmt(count, f),
+            Value::Average(average) => f.pad(&format!("{average:.2}")),
+        }
+    }
+}
+
+impl Counts {
+    pub(crate) fn get(&self, metric: Metric) -> Value {
+        let count = match metric {
+            Metric::Lines => self.lines,
+            Metric::Words => self.words,
+            Metric::UnicodeWords => self.unicode_words,
+            Metric::Chars => self.chars,
+            Metric::Graphemes => self.graphemes,
+            Metric::Bytes => self.bytes,
+            Metric::CompressedBytes => self.compressed_bytes,
+            Metric::MaxLineLength => self.max_line_length,
+            Metric::Records => self.records,
+            Metric::WordsPerRecord => return self.per_record(self.record_words),
+            Metric::CharsPerRecord => return self.per_record(self.record_chars),
+        };
+
+        Value::Count(count)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn per_record(&self, count: usize) -> Value {
+        if self.records == 0 {
+            Value::Average(0.0)
+        } else {
+            Value::Average(count as f64 / self.records as f64)
+        }
+    }
+
+    /// Adds counts of another input to the total.
+    ///
+    /// The maximum line length of the total is the maximum among the inputs, not the sum.
+    pub(crate) fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.unicode_words += other.unicode_words;
+        self.chars += other.chars;
+        self.graphemes += other.graphemes;
+        self.bytes += other.bytes;
+        self.compressed_bytes += other.compressed_bytes;
+        self.max_line_length = self.max_line_length.max(other.max_line_length);
+        self.records += other.records;
+        self.record_words += other.record_words;
+        self.record_chars += other.record_chars;
+    }
+}
+
+/// Width of a line after a part of it without line breaks,
+/// as a function of the width before that part.
+///
+/// Needed to stitch the line length when a line is split between ranges counted in parallel:
+/// because of tab stops, the width of the continuation depends on where the line started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WidthTransform {
+    /// The width grows by the given number of columns.
+    Shift(usize),
+    /// The width moves to the next tab stop after `before` columns, then grows by `after` columns.
+    TabStop { before: usize, after: usize },
+}
+
+impl WidthTransform {
+    fn apply(self, width: usize) -> usize {
+        match self {
+            WidthTransform::Shift(columns) => width + columns,
+            WidthTransform::TabStop { before, after } => next_tab_stop(width + before) + after,
+        }
+    }
+
+    fn push(self, char: char) -> Self {
+        match (self, char) {
+            (WidthTransform::Shift(before), '\t') => WidthTransform::TabStop { before, after: 0 },
+            // the width after a tab stop is a multiple of the tab width,
+            // so the next tab stop only depends on the columns after it
+            (WidthTransform::TabStop { before, after }, '\t') => WidthTransform::TabStop {
+                before,
+                after: next_tab_stop(after),
+            },
+            (WidthTransform::Shift(columns), char) => {
+                WidthTransform::Shift(columns + char_width(char))
+            }
+            (WidthTransform::TabStop { before, after }, char) => WidthTransform::TabStop {
+                before,
+                after: after + char_width(char),
+            },
+        }
+    }
+}
+
+/// Width of the current line.
+#[derive(Debug, Clone, Copy)]
+enum LineWidth {
+    Known(usize),
+    /// The counter started in the middle of a line and hasn't seen a line break yet.
+    Relative(WidthTransform),
+}
+
+/// Lines seen by the counter, kept with [`CountOptions::keep_lines`].
+#[derive(Debug, Default)]
+struct LineBuffer {
+    /// Characters of the current line, without the line feed.
+    current: String,
+    complete: Vec<String>,
+}
+
+/// Incremental counter, the input is fed to it in chunks of any size.
+///
+/// Everything that depends on previous chunks is kept between calls of [`Counter::update`]:
+/// whether the last character was inside a word, the width of the current line
+/// and the first bytes of a multi-byte character split between chunks.
+#[derive(Debug)]
+pub(crate) struct Counter {
+    invalid_utf8: InvalidUtf8,
+    /// Lines are counted by line breaks, the last line without a break is added in [`Counter::finish`].
+    counts: Counts,
+    in_word: bool,
+    /// Whether the first counted character is a part of a word, `None` before any character.
+    starts_in_word: Option<bool>,
+    at_line_start: bool,
+    line_width: LineWidth,
+    /// Width of the part before the first line break, for counters started in the middle of a line.
+    head_width: Option<WidthTransform>,
+    segmenter: Option<LineSegmenter>,
+    records: Option<RecordCounter>,
+    lines: Option<LineBuffer>,
+    /// Beginning of an incomplete UTF-8 sequence at the end of the previous chunk.
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl Counter {
+    pub(crate) fn new(options: &CountOptions) -> Self {
+        Self {
+            invalid_utf8: options.invalid_utf8,
+            counts: Counts::default(),
+            in_word: false,
+            starts_in_word: None,
+            at_line_start: true,
+            line_width: LineWidth::Known(0),
+            head_width: None,
+            segmenter: options.segment.then(LineSegmenter::default),
+            records: options.records.clone().map(RecordCounter::new),
+            lines: options.keep_lines.then(LineBuffer::default),
+            pending: [0; 4],
+            pending_len: 0,
+        }
+    }
+
+    /// Counter for a part of the input that starts at an unknown position of a line.
+    ///
+    /// It's combined with the counter of the preceding part with [`Counter::absorb`].
+    /// Records and kept lines can't be stitched, so continuations don't have them.
+    pub(crate) fn continuation(options: &CountOptions) -> Self {
+        Self {
+            line_width: LineWidth::Relative(WidthTransform::Shift(0)),
+            segmenter: options.segment.then(LineSegmenter::continuation),
+            records: None,
+            lines: None,
+            ..Self::new(options)
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut bytes: &[u8]) {
+        self.counts.bytes += bytes.len();
+
+        // complete the character started in the previous chunk
+        while self.pending_len > 0 {
+            let Some((&byte, rest)) = bytes.split_first() else {
+                return;
+            };
+            self.pending[self.pending_len] = byte;
+
+            match std::str::from_utf8(&self.pending[..=self.pending_len]) {
+                Ok(str) => {
+                    let char = str.chars().next().expect("one character");
+                    self.pending_len = 0;
+                    bytes = rest;
+                    self.char(char);
+                }
+                Err(err) if err.error_len().is_none() => {
+                    self.pending_len += 1;
+                    bytes = rest;
+                }
+                Err(_) => {
+                    // the byte doesn't continue the sequence, it's counted on its own below
+                    self.invalid(self.pending_len);
+                    self.pending_len = 0;
+                }
+            }
+        }
+
+        let mut chunks = bytes.utf8_chunks().peekable();
+        while let Some(chunk) = chunks.next() {
+            for char in chunk.valid().chars() {
+                self.char(char);
+            }
+
+            let invalid = chunk.invalid();
+            if invalid.is_empty() {
+                continue;
+            }
+
+            let is_incomplete = chunks.peek().is_none()
+                && std::str::from_utf8(invalid).is_err_and(|err| err.error_len().is_none());
+            if is_incomplete {
+                self.pending[..invalid.len()].copy_from_slice(invalid);
+                self.pending_len = invalid.len();
+            } else {
+                self.invalid(invalid.len());
+            }
+        }
+    }
+
+    /// Takes the lines ended by a line feed so far, with [`CountOptions::keep_lines`].
+    pub(crate) fn take_lines(&mut self) -> Vec<String> {
+        self.lines
+            .as_mut()
+            .map(|lines| std::mem::take(&mut lines.complete))
+            .unwrap_or_default()
+    }
+
+    /// Takes the last line at the end of the input if it has no line feed,
+    /// it's counted as a line by [`Counter::finish`].
+    pub(crate) fn take_last_line(&mut self) -> Option<String> {
+        self.flush_pending();
+
+        let lines = self.lines.as_mut()?;
+        (!self.at_line_start).then(|| std::mem::take(&mut lines.current))
+    }
+
+    pub(crate) fn finish(mut self) -> Counts {
+        self.flush_pending();
+
+        // the last line is counted even without the trailing newline
+        if !self.at_line_start {
+            self.counts.lines += 1;
+        }
+
+        if let Some(mut segmenter) = self.segmenter {
+            segmenter.finish();
+            self.counts.unicode_words = segmenter.words;
+            self.counts.graphemes = segmenter.graphemes;
+        }
+
+        if let Some(mut records) = self.records {
+            records.finish();
+            self.counts.records = records.records;
+            self.counts.record_words = records.words;
+            self.counts.record_chars = records.chars;
+        }
+
+        self.counts
+    }
+
+    /// Continues counting with the counts of the part of the input that immediately follows,
+    /// as if its bytes were passed to [`Counter::update`].
+    ///
+    /// The part must start at a character boundary.
+    pub(crate) fn absorb(&mut self, mut next: Counter) {
+        assert!(
+            self.records.is_none() && self.lines.is_none(),
+            "records and lines are kept on a single thread"
+        );
+        self.flush_pending();
+        next.flush_pending();
+
+        let joined_word = self.in_word && next.starts_in_word == Some(true);
+        self.counts.lines += next.counts.lines;
+        self.counts.words += next.counts.words - usize::from(joined_word);
+        self.counts.chars += next.counts.chars;
+        self.counts.bytes += next.counts.bytes;
+
+        if next.starts_in_word.is_some() {
+            self.in_word = next.in_word;
+            self.at_line_start = next.at_line_start;
+        }
+
+        let head_width = next.head_width.or(match next.line_width {
+            LineWidth::Relative(transform) => Some(transform),
+            LineWidth::Known(_) => None,
+        });
+        if let Some(head_width) = head_width {
+            self.line_width = self.line_width.push_transform(head_width);
+            self.update_max_line_length();
+        }
+        if let LineWidth::Known(width) = next.line_width {
+            self.line_width = LineWidth::Known(width);
+        }
+        self.counts.max_line_length = self.counts.max_line_length.max(next.counts.max_line_length);
+
+        if let (Some(segmenter), Some(next)) = (&mut self.segmenter, next.segmenter) {
+            segmenter.absorb(next);
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        if self.pending_len > 0 {
+            self.invalid(self.pending_len);
+            self.pending_len = 0;
+        }
+    }
+
+    fn char(&mut self, char: char) {
+        self.counts.chars += 1;
+        self.at_line_start = false;
+
+        if char.is_whitespace() {
+            self.in_word = false;
+        } else if !self.in_word {
+            self.in_word = true;
+            self.counts.words += 1;
+        }
+        self.starts_in_word.get_or_insert(self.in_word);
+
+        if let Some(segmenter) = &mut self.segmenter {
+            segmenter.push(char);
+        }
+        if let Some(records) = &mut self.records {
+            records.push(char);
+        }
+
+        if let Some(lines) = &mut self.lines {
+            if char == '\n' {
+                let line = std::mem::take(&mut lines.current);
+                lines.complete.push(line);
+            } else {
+                lines.current.push(char);
+            }
+        }
+
+        match char {
+            '\n' | '\r' => {
+                if char == '\n' {
+                    self.counts.lines += 1;
+                    self.at_line_start = true;
+                }
+                if let LineWidth::Relative(transform) = self.line_width {
+                    self.head_width = Some(transform);
+                }
+                self.line_width = LineWidth::Known(0);
+            }
+            char => self.line_width = self.line_width.push(char),
+        }
+
+        self.update_max_line_length();
+    }
+
+    fn update_max_line_length(&mut self) {
+        if let LineWidth::Known(width) = self.line_width {
+            self.counts.max_line_length = self.counts.max_line_length.max(width);
+        }
+    }
+
+    /// Counts an invalid UTF-8 sequence of `len` bytes according to the policy.
+    fn invalid(&mut self, len: usize) {
+        match self.invalid_utf8 {
+            InvalidUtf8::Replace => self.char(char::REPLACEMENT_CHARACTER),
+            InvalidUtf8::Bytes => {
+                for _ in 0..len {
+                    self.char(char::REPLACEMENT_CHARACTER);
+                }
+            }
+            InvalidUtf8::Skip => {}
+        }
+    }
+}
+
+impl LineWidth {
+    fn push(self, char: char) -> Self {
+        match self {
+            LineWidth::Known(width) => {
+                LineWidth::Known(WidthTransform::Shift(0).push(char).apply(width))
+            }
+            LineWidth::Relative(transform) => LineWidth::Relative(transform.push(char)),
+        }
+    }
+
+    fn push_transform(self, next: WidthTransform) -> Self {
+        match self {
+            LineWidth::Known(width) => LineWidth::Known(next.apply(width)),
+            LineWidth::Relative(_) => unreachable!("absorbing counter starts at a line start"),
+        }
+    }
+}
+
+fn next_tab_stop(width: usize) -> usize {
+    width + TAB_WIDTH - width % TAB_WIDTH
+}
+
+fn char_width(char: char) -> usize {
+    char.width().unwrap_or_default()
+}
+
+/// Counts objects in the input, reading it with a fixed-size buffer.
+pub(crate) fn count_reader(reader: impl Read, options: &CountOptions) -> io::Result<Counts> {
+    count_reader_with_buffer(reader, options, BUFFER_SIZE)
+}
+
+fn count_reader_with_buffer(
+    mut reader: impl Read,
+    options: &CountOptions,
+    buffer_size: usize,
+) -> io::Result<Counts> {
+    let mut counter = Counter::new(options);
+    let mut buffer = vec![0; buffer_size];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        counter.update(&buffer[..read]);
+    }
+
+    Ok(counter.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use super::*;
+
+    /// Counts a whole string at once, the streaming counter must give the same result.
+    fn reference_counts(content: &str) -> Counts {
+        let max_line_length = content
+            .lines()
+            .map(|line| {
+                let mut counter = Counter::new(&CountOptions::default());
+                counter.update(line.as_bytes());
+                counter.finish().max_line_length
+            })
+            .max()
+            .unwrap_or_default();
+
+        Counts {
+            lines: content.lines().count(),
+            words: content.split_whitespace().count(),
+            chars: content.chars().count(),
+            bytes: content.len(),
+            max_line_length,
+            ..Counts::default()
+        }
+    }
+
+    fn count_bytes(content: &[u8], invalid_utf8: InvalidUtf8, buffer_size: usize) -> Counts {
+        let options = CountOptions {
+            invalid_utf8,
+            ..CountOptions::default()
+        };
+        count_reader_with_buffer(content, &options, buffer_size).expect("reading from slice")
+    }
+
+    fn assert_counts(actual: &str, expected: &Expect) {
+        let actual = count_bytes(actual.as_bytes(), InvalidUtf8::Replace, BUFFER_SIZE);
+        expected.assert_debug_eq(&actual);
+    }
+
+    #[test]
+    fn test_counts() {
+        assert_counts(
+            "",
+            &expect![[r#"
+                Counts {
+                    lines: 0,
+                    words: 0,
+                    unicode_words: 0,
+                    chars: 0,
+                    graphemes: 0,
+                    bytes: 0,
+                    compressed_bytes: 0,
+                    max_line_length: 0,
+                    records: 0,
+                    record_words: 0,
+                    record_chars: 0,
+                }
+            "#]],
+        );
+
+        assert_counts(
+            "a b c\nd e\n",
+            &expect![[r#"
+                Counts {
+                    lines: 2,
+                    words: 5,
+                    unicode_words: 0,
+                    chars: 10,
+                    graphemes: 0,
+                    bytes: 10,
+                    compressed_bytes: 0,
+                    max_line_length: 5,
+                    records: 0,
+                    record_words: 0,
+                    record_chars: 0,
+                }
+            "#]],
+        );
+
+        assert_counts(
+            "привет мир\n日本語\n",
+            &expect![[r#"
+                Counts {
+                    lines: 2,
+                    words: 3,
+                    unicode_words: 0,
+                    chars: 15,
+                    graphemes: 0,
+                    bytes: 30,
+                    compressed_bytes: 0,
+                    max_line_length: 10,
+                    records: 0,
+                    record_words: 0,
+                    record_chars: 0,
+                }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn test_line_display_width() {
+        let max_line_length = |line: &str| {
+            count_bytes(line.as_bytes(), InvalidUtf8::Replace, BUFFER_SIZE).max_line_length
+        };
+
+        assert_eq!(max_line_length("a\tb"), 9);
+        assert_eq!(max_line_length("12345678\t"), 16);
+        assert_eq!(max_line_length("long line\rab"), 9);
+        assert_eq!(max_line_length("日本"), 4);
+    }
+
+    #[test]
+    fn test_streaming_matches_reference() {
+        let contents = [
+            "",
+            "\n",
+            "word",
+            "no trailing newline\nsecond",
+            "  leading and trailing spaces  \n\n\n",
+            "crlf\r\nlines\r\n",
+            "tabs\tand\u{a0}no-break\u{2003}spaces\n",
+            "привет мир\n日本語 🦀🦀\ne\u{301}\n",
+        ];
+
+        for content in contents {
+            let expected = reference_counts(content);
+            for buffer_size in [1, 2, 3, 5, 7, BUFFER_SIZE] {
+                let actual = count_bytes(content.as_bytes(), InvalidUtf8::Replace, buffer_size);
+                assert_eq!(actual, expected, "{content:?} with buffer of {buffer_size}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        // "ab", invalid continuation byte, truncated 3-byte sequence, " cd", truncated at the end
+        let content = b"ab\x80\xe6\x97 cd\xf0\x9f";
+
+        for buffer_size in [1, 2, 3, BUFFER_SIZE] {
+            let replace = count_bytes(content, InvalidUtf8::Replace, buffer_size);
+            let lossy = String::from_utf8_lossy(content);
+            assert_eq!(replace.chars, lossy.chars().count());
+            assert_eq!(replace.words, lossy.split_whitespace().count());
+            assert_eq!(replace.bytes, content.len());
+
+            let bytes = count_bytes(content, InvalidUtf8::Bytes, buffer_size);
+            assert_eq!(bytes.chars, 10);
+            assert_eq!(bytes.words, 2);
+
+            let skip = count_bytes(content, InvalidUtf8::Skip, buffer_size);
+            assert_eq!(skip.chars, 5);
+            assert_eq!(skip.words, 2);
+            assert_eq!(skip.max_line_length, 5);
+        }
+    }
+
+    #[test]
+    fn test_records() {
+        // "\x1e\n" is split between chunks with the smaller buffers
+        let content = "first record\x1e\nsecond one\x1e\nпоследняя запись";
+        let options = CountOptions {
+            records: Some(RecordSeparator::Delimiter(vec!['\x1e', '\n'])),
+            ..CountOptions::default()
+        };
+
+        for buffer_size in [1, 2, 3, BUFFER_SIZE] {
+            let counts = count_reader_with_buffer(content.as_bytes(), &options, buffer_size)
+                .expect("reading from slice");
+            assert_eq!(
+                (counts.records, counts.record_words, counts.record_chars),
+                (3, 6, 38)
+            );
+            assert_eq!(counts.get(Metric::CharsPerRecord).to_string(), "12.67");
+        }
+    }
+}
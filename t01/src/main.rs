@@ -1,60 +1,315 @@
-use std::process::ExitCode;
+use std::{num::NonZeroUsize, process::ExitCode};
 
-use anyhow::Result;
-use camino::Utf8PathBuf;
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
 use clap::Parser;
+use counter::{CountOptions, Counts, InvalidUtf8, Metric};
+use output::{Format, InputError, Report, Row};
+use records::RecordOptions;
+use sloc::{Language, SlocReport};
+use walk::{WalkOptions, Walker};
+
+mod compress;
+mod counter;
+mod output;
+mod parallel;
+mod records;
+mod segment;
+mod sloc;
+mod walk;
 
 #[derive(Debug, Parser)]
-/// Utility for counting the number of objects in a file.
+/// Utility for counting the number of objects in files.
 struct Args {
-    /// Path to the text file to analyze.
-    path: Utf8PathBuf,
+    /// Paths to the text files to analyze. With no path, or when path is `-`, read standard input.
+    paths: Vec<Utf8PathBuf>,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "paths")]
+    /// Read NUL-separated paths to analyze from the file, `-` for standard input.
+    files0_from: Option<Utf8PathBuf>,
 
     #[clap(flatten)]
     flags: Flags,
+
+    #[clap(flatten)]
+    walk: WalkOptions,
+
+    #[clap(flatten)]
+    records: RecordOptions,
+
+    /// How to count bytes that are not valid UTF-8.
+    #[arg(long, value_enum, default_value_t)]
+    invalid_utf8: InvalidUtf8,
+
+    /// Count the decompressed content of gzip, zstd and xz files detected by their magic bytes,
+    /// and report both the decompressed and the compressed number of bytes.
+    #[arg(short = 'z', long)]
+    decompress: bool,
+
+    /// Number of threads counting a single file.
+    #[arg(short = 'j', long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    /// Count code, comment and blank lines per language detected by the file extension,
+    /// instead of the other metrics. Files of unknown languages are skipped, and the standard
+    /// input is an error. Python docstrings first on their line are comments.
+    #[arg(long)]
+    sloc: bool,
+
+    /// Format of the report.
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
 }
 
+/// Flags can be combined, the columns are always printed in the order:
+/// lines, words, Unicode words, characters, graphemes, bytes, compressed bytes,
+/// maximum line length, followed by records and averages per record when records are counted.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
-#[group(multiple = false)]
 struct Flags {
-    #[arg(short, long)]
-    /// Count number of characters.
-    characters: bool,
-
     #[arg(short, long)]
     /// Count number of lines.
     lines: bool,
 
-    #[arg(short, long)]
-    /// Count number of words. [Default - If no flag is specified, this flag is selected]
-    words: bool,
+    #[arg(
+        short,
+        long,
+        value_enum,
+        value_name = "MODE",
+        value_delimiter = ',',
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "whitespace"
+    )]
+    /// Count number of words, separated by whitespace or by Unicode word boundaries.
+    /// Both modes can be selected with `--words=whitespace,unicode`.
+    /// [Default - If no flag is specified, this flag is selected]
+    words: Vec<WordMode>,
+
+    #[arg(short = 'm', long)]
+    /// Count number of characters (Unicode scalar values).
+    chars: bool,
+
+    #[arg(long)]
+    /// Count number of extended grapheme clusters (user-perceived characters).
+    graphemes: bool,
+
+    #[arg(short = 'c', long)]
+    /// Count number of bytes.
+    bytes: bool,
+
+    #[arg(short = 'L', long)]
+    /// Print the display width of the longest line.
+    max_line_length: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum WordMode {
+    /// Sequences of non-whitespace characters, as `wc` counts them.
+    Whitespace,
+    /// Words by Unicode word boundaries (UAX #29): every ideograph is a word,
+    /// punctuation is not.
+    Unicode,
+}
+
+impl Flags {
+    /// Selected metrics in the order of the report columns.
+    fn metrics(&self) -> Vec<Metric> {
+        let mut metrics = [
+            (self.lines, Metric::Lines),
+            (self.words.contains(&WordMode::Whitespace), Metric::Words),
+            (
+                self.words.contains(&WordMode::Unicode),
+                Metric::UnicodeWords,
+            ),
+            (self.chars, Metric::Chars),
+            (self.graphemes, Metric::Graphemes),
+            (self.bytes, Metric::Bytes),
+            (self.max_line_length, Metric::MaxLineLength),
+        ]
+        .into_iter()
+        .filter_map(|(selected, metric)| selected.then_some(metric))
+        .collect::<Vec<_>>();
+        metrics.dedup();
+
+        if metrics.is_empty() {
+            vec![Metric::Words]
+        } else {
+            metrics
+        }
+    }
+}
+
+fn count_input(
+    path: Option<&Utf8Path>,
+    threads: NonZeroUsize,
+    decompress: bool,
+    options: &CountOptions,
+) -> Result<Counts> {
+    match path {
+        Some(path) if path != "-" => if decompress {
+            compress::count_file(path, threads, options)
+        } else {
+            parallel::count_file(path, threads, options)
+        }
+        .with_context(|| path.to_string()),
+        _ => {
+            let stdin = std::io::stdin().lock();
+            if decompress {
+                compress::count_reader(stdin, options)
+            } else {
+                counter::count_reader(stdin, options)
+            }
+            .context("standard input")
+        }
+    }
+}
+
+/// Paths given on the command line or in `--files0-from`, with directories expanded.
+fn collect_inputs(args: &Args) -> Result<Vec<Result<Option<Utf8PathBuf>, InputError>>> {
+    let walker = Walker::new(&args.walk)?;
+
+    let paths = match &args.files0_from {
+        Some(files0_from) => {
+            let paths = walk::read_files0_from(files0_from)?;
+            if paths.is_empty() {
+                return Ok(Vec::new());
+            }
+            paths
+        }
+        None => args.paths.clone(),
+    };
+
+    Ok(walker.inputs(paths))
+}
+
+fn print_error(error: &InputError) {
+    match &error.name {
+        Some(name) => eprintln!("t01: {name}: {}", error.message),
+        None => eprintln!("t01: {}", error.message),
+    }
 }
 
-fn count_objects(args: Args) -> Result<usize> {
-    let file_content = std::fs::read_to_string(args.path)?;
-    let flags = (args.flags.characters, args.flags.lines, args.flags.words);
-    let number = match flags {
-        (true, false, false) => file_content.len(),
-        (false, true, false) => file_content.lines().count(),
-        (false, false, true | false) => file_content.split_whitespace().count(),
-        _ => unreachable!(),
+fn count_all(args: &Args, inputs: Vec<Result<Option<Utf8PathBuf>, InputError>>) -> ExitCode {
+    let records = match args.records.separator() {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("t01: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut metrics = args.flags.metrics();
+    if records.is_some() {
+        metrics.extend([
+            Metric::Records,
+            Metric::WordsPerRecord,
+            Metric::CharsPerRecord,
+        ]);
+    }
+    if args.decompress {
+        metrics.extend([Metric::Bytes, Metric::CompressedBytes]);
+    }
+    metrics.sort();
+    metrics.dedup();
+    let options = CountOptions {
+        invalid_utf8: args.invalid_utf8,
+        segment: metrics.contains(&Metric::UnicodeWords) || metrics.contains(&Metric::Graphemes),
+        records,
+        keep_lines: false,
     };
 
-    Ok(number)
+    let mut report = Report::default();
+
+    for input in inputs {
+        let error = match input {
+            Ok(path) => {
+                let name = path.as_ref().map(ToString::to_string);
+                match count_input(path.as_deref(), args.threads, args.decompress, &options) {
+                    Ok(counts) => {
+                        report.rows.push(Row { counts, name });
+                        continue;
+                    }
+                    Err(err) => InputError {
+                        name,
+                        message: err.root_cause().to_string(),
+                    },
+                }
+            }
+            Err(error) => error,
+        };
+
+        print_error(&error);
+        report.errors.push(error);
+    }
+
+    print!("{}", report.format(args.format, &metrics));
+
+    if report.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Counts lines of source files, the standard input has no extension and is an error.
+fn count_sloc(args: &Args, inputs: Vec<Result<Option<Utf8PathBuf>, InputError>>) -> ExitCode {
+    let mut report = SlocReport::default();
+
+    for input in inputs {
+        let error = match input {
+            Ok(Some(path)) if path != "-" => {
+                let Some(language) = Language::from_path(&path) else {
+                    continue;
+                };
+                let counts = std::fs::File::open(&path)
+                    .and_then(|file| sloc::count_reader(std::io::BufReader::new(file), language));
+                match counts {
+                    Ok(counts) => {
+                        report.add(language, &counts);
+                        continue;
+                    }
+                    Err(err) => InputError {
+                        name: Some(path.to_string()),
+                        message: err.to_string(),
+                    },
+                }
+            }
+            Ok(_) => InputError {
+                name: Some("-".to_owned()),
+                message: "cannot detect language".to_owned(),
+            },
+            Err(error) => error,
+        };
+
+        print_error(&error);
+        report.errors.push(error);
+    }
+
+    print!("{}", report.format(args.format));
+
+    if report.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
 
 fn main() -> ExitCode {
     let args = Args::parse();
 
-    match count_objects(args) {
-        Ok(number) => {
-            println!("{number}");
-            ExitCode::SUCCESS
-        }
+    let inputs = match collect_inputs(&args) {
+        Ok(inputs) => inputs,
         Err(err) => {
-            eprintln!("{err}");
-            ExitCode::FAILURE
+            eprintln!("t01: {err:#}");
+            return ExitCode::FAILURE;
         }
+    };
+
+    if args.sloc {
+        count_sloc(&args, inputs)
+    } else {
+        count_all(&args, inputs)
     }
 }
 
@@ -76,7 +331,7 @@ mod tests {
         assert_from_iter(
             args,
             &expect![[
-                r#"Args { path: "./test.txt", flags: Flags { characters: true, lines: false, words: false } }"#
+                r#"Args { paths: ["./test.txt"], files0_from: None, flags: Flags { lines: false, words: [], chars: false, graphemes: false, bytes: true, max_line_length: false }, walk: WalkOptions { recursive: false, include: [], exclude: [], no_ignore: false }, records: RecordOptions { record_delimiter: None, record_regex: None }, invalid_utf8: Replace, decompress: false, threads: 1, sloc: false, format: Plain }"#
             ]],
         );
 
@@ -84,7 +339,7 @@ mod tests {
         assert_from_iter(
             args,
             &expect![[
-                r#"Args { path: "./test.txt", flags: Flags { characters: false, lines: true, words: false } }"#
+                r#"Args { paths: ["./test.txt"], files0_from: None, flags: Flags { lines: true, words: [], chars: false, graphemes: false, bytes: false, max_line_length: false }, walk: WalkOptions { recursive: false, include: [], exclude: [], no_ignore: false }, records: RecordOptions { record_delimiter: None, record_regex: None }, invalid_utf8: Replace, decompress: false, threads: 1, sloc: false, format: Plain }"#
             ]],
         );
 
@@ -92,15 +347,47 @@ mod tests {
         assert_from_iter(
             args,
             &expect![[
-                r#"Args { path: "./test.txt", flags: Flags { characters: false, lines: false, words: true } }"#
+                r#"Args { paths: ["./test.txt"], files0_from: None, flags: Flags { lines: false, words: [Whitespace], chars: false, graphemes: false, bytes: false, max_line_length: false }, walk: WalkOptions { recursive: false, include: [], exclude: [], no_ignore: false }, records: RecordOptions { record_delimiter: None, record_regex: None }, invalid_utf8: Replace, decompress: false, threads: 1, sloc: false, format: Plain }"#
             ]],
         );
 
         assert_from_iter(
             common_args,
             &expect![[
-                r#"Args { path: "./test.txt", flags: Flags { characters: false, lines: false, words: false } }"#
+                r#"Args { paths: ["./test.txt"], files0_from: None, flags: Flags { lines: false, words: [], chars: false, graphemes: false, bytes: false, max_line_length: false }, walk: WalkOptions { recursive: false, include: [], exclude: [], no_ignore: false }, records: RecordOptions { record_delimiter: None, record_regex: None }, invalid_utf8: Replace, decompress: false, threads: 1, sloc: false, format: Plain }"#
             ]],
         );
     }
+
+    #[test]
+    fn test_paths() {
+        assert_from_iter(
+            ["t01", "-l"],
+            &expect!["Args { paths: [], files0_from: None, flags: Flags { lines: true, words: [], chars: false, graphemes: false, bytes: false, max_line_length: false }, walk: WalkOptions { recursive: false, include: [], exclude: [], no_ignore: false }, records: RecordOptions { record_delimiter: None, record_regex: None }, invalid_utf8: Replace, decompress: false, threads: 1, sloc: false, format: Plain }"],
+        );
+
+        assert_from_iter(
+            ["t01", "a.txt", "-", "b.txt"],
+            &expect![[
+                r#"Args { paths: ["a.txt", "-", "b.txt"], files0_from: None, flags: Flags { lines: false, words: [], chars: false, graphemes: false, bytes: false, max_line_length: false }, walk: WalkOptions { recursive: false, include: [], exclude: [], no_ignore: false }, records: RecordOptions { record_delimiter: None, record_regex: None }, invalid_utf8: Replace, decompress: false, threads: 1, sloc: false, format: Plain }"#
+            ]],
+        );
+    }
+
+    #[test]
+    fn test_metrics_order() {
+        let args = Args::parse_from(["t01", "-L", "-c", "-l", "-m", "-w", "--graphemes"]);
+        expect!["[Lines, Words, Chars, Graphemes, Bytes, MaxLineLength]"]
+            .assert_eq(&format!("{:?}", args.flags.metrics()));
+
+        let args = Args::parse_from(["t01"]);
+        expect![[r#"[Words]"#]].assert_eq(&format!("{:?}", args.flags.metrics()));
+
+        let args = Args::parse_from(["t01", "--words=unicode", "file.txt"]);
+        expect![[r#"[UnicodeWords]"#]].assert_eq(&format!("{:?}", args.flags.metrics()));
+
+        let args = Args::parse_from(["t01", "-w", "file.txt", "--words=unicode,whitespace"]);
+        expect![[r#"[Words, UnicodeWords]"#]].assert_eq(&format!("{:?}", args.flags.metrics()));
+        expect![[r#"["file.txt"]"#]].assert_eq(&format!("{:?}", args.paths));
+    }
 }
@@ -0,0 +1,397 @@
+use serde::Serialize;
+
+use crate::counter::{Counts, Metric, Value};
+
+/// Format of the report.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Format {
+    /// Columns of numbers followed by the name of the input, as `wc` prints them.
+    #[default]
+    Plain,
+    /// A single JSON object with counts of every input, the total and the errors.
+    Json,
+    /// A header with the names of the metrics and a row for every input.
+    Csv,
+}
+
+/// Counts of a single input.
+///
+/// The name is absent when standard input is read implicitly (no paths given),
+/// the same way `wc` prints bare numbers in that case.
+#[derive(Debug, Clone)]
+pub(crate) struct Row {
+    pub(crate) counts: Counts,
+    pub(crate) name: Option<String>,
+}
+
+/// An input that couldn't be counted.
+#[derive(Debug)]
+pub(crate) struct InputError {
+    pub(crate) name: Option<String>,
+    pub(crate) message: String,
+}
+
+/// Results of counting all the inputs.
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    pub(crate) rows: Vec<Row>,
+    pub(crate) errors: Vec<InputError>,
+}
+
+/// Counts of the selected metrics, the absent ones are not serialized.
+#[derive(Debug, Serialize)]
+struct JsonCounts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lines: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unicode_words: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chars: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    graphemes: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compressed_bytes: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_line_length: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words_per_record: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chars_per_record: Option<Value>,
+}
+
+impl JsonCounts {
+    fn new(counts: &Counts, metrics: &[Metric]) -> Self {
+        let get = |metric| metrics.contains(&metric).then(|| counts.get(metric));
+
+        Self {
+            lines: get(Metric::Lines),
+            words: get(Metric::Words),
+            unicode_words: get(Metric::UnicodeWords),
+            chars: get(Metric::Chars),
+            graphemes: get(Metric::Graphemes),
+            bytes: get(Metric::Bytes),
+            compressed_bytes: get(Metric::CompressedBytes),
+            max_line_length: get(Metric::MaxLineLength),
+            records: get(Metric::Records),
+            words_per_record: get(Metric::WordsPerRecord),
+            chars_per_record: get(Metric::CharsPerRecord),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonFile<'report> {
+    name: Option<&'report str>,
+    #[serde(flatten)]
+    counts: JsonCounts,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonError<'report> {
+    name: Option<&'report str>,
+    error: &'report str,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonReport<'report> {
+    files: Vec<JsonFile<'report>>,
+    total: JsonCounts,
+    errors: Vec<JsonError<'report>>,
+}
+
+impl Report {
+    pub(crate) fn total(&self) -> Counts {
+        let mut total = Counts::default();
+        for row in &self.rows {
+            total.add(&row.counts);
+        }
+
+        total
+    }
+
+    pub(crate) fn format(&self, format: Format, metrics: &[Metric]) -> String {
+        match format {
+            Format::Plain => format_plain(&self.rows_with_total(), metrics),
+            Format::Json => self.format_json(metrics),
+            Format::Csv => format_csv(&self.rows_with_total(), metrics),
+        }
+    }
+
+    /// Rows of the inputs followed by the `total` row when there is more than one input.
+    fn rows_with_total(&self) -> Vec<Row> {
+        let mut rows = self.rows.clone();
+
+        if self.rows.len() + self.errors.len() > 1 {
+            rows.push(Row {
+                counts: self.total(),
+                name: Some("total".to_owned()),
+            });
+        }
+
+        rows
+    }
+
+    fn format_json(&self, metrics: &[Metric]) -> String {
+        let report = JsonReport {
+            files: self
+                .rows
+                .iter()
+                .map(|row| JsonFile {
+                    name: row.name.as_deref(),
+                    counts: JsonCounts::new(&row.counts, metrics),
+                })
+                .collect(),
+            total: JsonCounts::new(&self.total(), metrics),
+            errors: self
+                .errors
+                .iter()
+                .map(|error| JsonError {
+                    name: error.name.as_deref(),
+                    error: &error.message,
+                })
+                .collect(),
+        };
+
+        serde_json::to_string_pretty(&report).expect("report is serializable") + "\n"
+    }
+}
+
+/// Formats rows in the `wc` layout: numbers of the selected metrics are right-aligned
+/// to the width of the widest number, followed by the name of the input.
+fn format_plain(rows: &[Row], metrics: &[Metric]) -> String {
+    let width = if rows.len() > 1 || metrics.len() > 1 {
+        rows.iter()
+            .flat_map(|row| metrics.iter().map(|&metric| row.counts.get(metric)))
+            .map(|number| number.to_string().len())
+            .max()
+            .unwrap_or_default()
+    } else {
+        0
+    };
+
+    rows.iter()
+        .map(|row| {
+            let mut columns = metrics
+                .iter()
+                .map(|&metric| format!("{:>width$}", row.counts.get(metric)))
+                .collect::<Vec<_>>();
+            columns.extend(row.name.clone());
+
+            columns.join(" ") + "\n"
+        })
+        .collect()
+}
+
+fn format_csv(rows: &[Row], metrics: &[Metric]) -> String {
+    let header = std::iter::once("name")
+        .chain(metrics.iter().map(|metric| metric.name()))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let rows = rows.iter().map(|row| {
+        let name = row.name.as_deref().map(csv_field).unwrap_or_default();
+        std::iter::once(name)
+            .chain(
+                metrics
+                    .iter()
+                    .map(|&metric| row.counts.get(metric).to_string()),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+
+    std::iter::once(header)
+        .chain(rows)
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Quotes the field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    use super::*;
+
+    fn report() -> Report {
+        let counts = |content: &str| {
+            crate::counter::count_reader(
+                content.as_bytes(),
+                &crate::counter::CountOptions::default(),
+            )
+            .expect("reading from slice")
+        };
+
+        Report {
+            rows: vec![
+                Row {
+                    counts: counts(&"a b c\nd e\n".repeat(6)),
+                    name: Some("report.txt".to_owned()),
+                },
+                Row {
+                    counts: counts("hello world\n"),
+                    name: Some("-".to_owned()),
+                },
+            ],
+            errors: vec![InputError {
+                name: Some("missing, \"quoted\".txt".to_owned()),
+                message: "No such file or directory (os error 2)".to_owned(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_format_plain() {
+        let single = Report {
+            rows: vec![Row {
+                counts: Counts {
+                    words: 34423,
+                    ..Counts::default()
+                },
+                name: None,
+            }],
+            errors: Vec::new(),
+        };
+        expect![[r#"
+            34423
+        "#]]
+        .assert_eq(&single.format(Format::Plain, &[Metric::Words]));
+
+        let metrics = [
+            Metric::Lines,
+            Metric::Words,
+            Metric::Bytes,
+            Metric::MaxLineLength,
+        ];
+        expect![[r#"
+            12 30 60  5 report.txt
+             1  2 12 11 -
+            13 32 72 11 total
+        "#]]
+        .assert_eq(&report().format(Format::Plain, &metrics));
+
+        let records = Report {
+            rows: vec![
+                Row {
+                    counts: Counts {
+                        records: 3,
+                        record_words: 1000,
+                        ..Counts::default()
+                    },
+                    name: Some("records.txt".to_owned()),
+                },
+                Row {
+                    counts: Counts {
+                        records: 12,
+                        record_words: 18,
+                        ..Counts::default()
+                    },
+                    name: Some("-".to_owned()),
+                },
+            ],
+            errors: Vec::new(),
+        };
+        assert_eq!(
+            records.format(Format::Plain, &[Metric::Records, Metric::WordsPerRecord]),
+            "     3 333.33 records.txt\n    12   1.50 -\n    15  67.87 total\n"
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        expect![[r#"
+            {
+              "files": [
+                {
+                  "name": "report.txt",
+                  "lines": 12,
+                  "chars": 60
+                },
+                {
+                  "name": "-",
+                  "lines": 1,
+                  "chars": 12
+                }
+              ],
+              "total": {
+                "lines": 13,
+                "chars": 72
+              },
+              "errors": [
+                {
+                  "name": "missing, \"quoted\".txt",
+                  "error": "No such file or directory (os error 2)"
+                }
+              ]
+            }
+        "#]]
+        .assert_eq(&report().format(Format::Json, &[Metric::Lines, Metric::Chars]));
+    }
+
+    #[test]
+    fn test_format_json_compressed() {
+        let report = Report {
+            rows: vec![Row {
+                counts: Counts {
+                    lines: 40,
+                    bytes: 1200,
+                    compressed_bytes: 310,
+                    ..Counts::default()
+                },
+                name: Some("logs.gz".to_owned()),
+            }],
+            errors: Vec::new(),
+        };
+
+        expect![[r#"
+            {
+              "files": [
+                {
+                  "name": "logs.gz",
+                  "lines": 40,
+                  "bytes": 1200,
+                  "compressed_bytes": 310
+                }
+              ],
+              "total": {
+                "lines": 40,
+                "bytes": 1200,
+                "compressed_bytes": 310
+              },
+              "errors": []
+            }
+        "#]]
+        .assert_eq(&report.format(
+            Format::Json,
+            &[Metric::Lines, Metric::Bytes, Metric::CompressedBytes],
+        ));
+    }
+
+    #[test]
+    fn test_format_csv() {
+        expect![[r#"
+            name,words,max_line_length
+            report.txt,30,5
+            -,2,11
+            total,32,11
+        "#]]
+        .assert_eq(&report().format(Format::Csv, &[Metric::Words, Metric::MaxLineLength]));
+
+        assert_eq!(csv_field("a,b"), r#""a,b""#);
+        assert_eq!(csv_field(r#"say "hi""#), r#""say ""hi""""#);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{fs::File, io::BufReader, num::NonZeroUsize};
+
+use anyhow::Result;
+use camino::Utf8Path;
+use memmap2::Mmap;
+
+use crate::counter::{self, CountOptions, Counter, Counts};
+
+/// Ranges smaller than this are not worth a separate thread.
+const MIN_RANGE_SIZE: usize = 1024 * 1024;
+
+/// Maximum number of UTF-8 continuation bytes that follow the first byte of a character.
+const MAX_CONTINUATION_BYTES: usize = 3;
+
+/// Counts objects in the file, splitting it into ranges counted on separate threads.
+///
+/// Falls back to the streaming counter for files that can't be memory-mapped, like pipes,
+/// and when records are counted.
+pub(crate) fn count_file(
+    path: &Utf8Path,
+    threads: NonZeroUsize,
+    options: &CountOptions,
+) -> Result<Counts> {
+    count_buffered_file(BufReader::new(File::open(path)?), threads, options)
+}
+
+/// Like [`count_file`], for a file already opened, the bytes in the buffer are not lost.
+pub(crate) fn count_buffered_file(
+    reader: BufReader<File>,
+    threads: NonZeroUsize,
+    options: &CountOptions,
+) -> Result<Counts> {
+    let file = reader.get_ref();
+    if threads.get() == 1 || options.records.is_some() || !file.metadata()?.is_file() {
+        return Ok(counter::count_reader(reader, options)?);
+    }
+
+    // SAFETY: the file is only read, the counts are unspecified
+    // if another process modifies it while it's being counted
+    let mmap = unsafe { Mmap::map(file)? };
+
+    let ranges = threads.get().min(mmap.len() / MIN_RANGE_SIZE).max(1);
+
+    Ok(count_bytes(&mmap, ranges, options))
+}
+
+/// Splits bytes into `ranges` parts, counts each part on its own thread and stitches the results.
+fn count_bytes(bytes: &[u8], ranges: usize, options: &CountOptions) -> Counts {
+    let ranges = split_ranges(bytes, ranges);
+
+    let counters = std::thread::scope(|scope| {
+        let handles = ranges
+            .iter()
+            .enumerate()
+            .map(|(index, range)| {
+                scope.spawn(move || {
+                    let mut counter = if index == 0 {
+                        Counter::new(options)
+                    } else {
+                        Counter::continuation(options)
+                    };
+                    counter.update(range);
+                    counter
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("counting thread doesn't panic"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut counters = counters.into_iter();
+    let mut counter = counters.next().expect("at least one range");
+    for next in counters {
+        counter.absorb(next);
+    }
+
+    counter.finish()
+}
+
+/// Splits bytes into approximately equal ranges that start at character boundaries,
+/// so a multi-byte character is never split between ranges.
+fn split_ranges(bytes: &[u8], ranges: usize) -> Vec<&[u8]> {
+    let range_size = bytes.len().div_ceil(ranges.max(1)).max(1);
+
+    let mut result = Vec::with_capacity(ranges);
+    let mut rest = bytes;
+    while rest.len() > range_size {
+        let mut split = range_size;
+        // a character can't have more continuation bytes,
+        // further ones are invalid on their own and the split is safe
+        for _ in 0..MAX_CONTINUATION_BYTES {
+            if split < rest.len() && is_continuation_byte(rest[split]) {
+                split += 1;
+            }
+        }
+
+        let (range, tail) = rest.split_at(split);
+        result.push(range);
+        rest = tail;
+    }
+    if !rest.is_empty() || result.is_empty() {
+        result.push(rest);
+    }
+
+    result
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::InvalidUtf8;
+
+    #[test]
+    fn test_parallel_matches_streaming() {
+        let contents: [&[u8]; 7] = [
+            b"",
+            b"one line without newline",
+            b"a b c\nd e\n\nlast",
+            "привет мир\n日本語 🦀🦀\ne\u{301} x\n".as_bytes(),
+            b"\ttabs\tinside\t a\tlong line\t\t with\rcarriage\treturns\n\tnext",
+            "   spaces\u{a0}\u{2003}  between   words   ".as_bytes(),
+            b"invalid \xe6\x97 bytes\x80\x80\x80\x80 and \xf0\x9f\x98 truncated \xf0\x9f",
+        ];
+
+        for content in contents {
+            for invalid_utf8 in [InvalidUtf8::Replace, InvalidUtf8::Bytes, InvalidUtf8::Skip] {
+                let options = CountOptions {
+                    invalid_utf8,
+                    segment: true,
+                    records: None,
+                    keep_lines: false,
+                };
+                let expected = counter::count_reader(content, &options).expect("reading slice");
+                for ranges in 1..=content.len() + 1 {
+                    let actual = count_bytes(content, ranges, &options);
+                    assert_eq!(
+                        actual,
+                        expected,
+                        "{:?} split into {ranges} ranges with {options:?}",
+                        String::from_utf8_lossy(content),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_ranges() {
+        let content = "aж日🦀".as_bytes();
+        let ranges = split_ranges(content, content.len());
+
+        let ranges = ranges
+            .into_iter()
+            .map(|range| std::str::from_utf8(range).expect("split at character boundary"))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, ["a", "ж", "日", "🦀"]);
+    }
+}
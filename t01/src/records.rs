@@ -0,0 +1,269 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use regex::Regex;
+
+/// Options selecting what separates records, when they are counted.
+#[derive(Parser, Debug)]
+pub(crate) struct RecordOptions {
+    #[arg(
+        long,
+        value_name = "STRING",
+        allow_hyphen_values = true,
+        conflicts_with = "record_regex"
+    )]
+    /// Count records separated by the string, e.g. `\0` or `\x1e`.
+    /// The escapes `\0`, `\n`, `\r`, `\t`, `\\` and `\xHH` are supported.
+    /// An empty string separates paragraphs by blank lines, as `RS=""` does in `awk`.
+    pub(crate) record_delimiter: Option<String>,
+
+    #[arg(long, value_name = "REGEX", allow_hyphen_values = true)]
+    /// Count records separated by lines that match the regex, e.g. `^---$`.
+    pub(crate) record_regex: Option<String>,
+}
+
+impl RecordOptions {
+    pub(crate) fn separator(&self) -> Result<Option<RecordSeparator>> {
+        if let Some(delimiter) = &self.record_delimiter {
+            let delimiter = unescape(delimiter)
+                .with_context(|| format!("invalid record delimiter \"{delimiter}\""))?;
+            if delimiter.is_empty() {
+                return Ok(Some(RecordSeparator::Paragraph));
+            }
+            return Ok(Some(RecordSeparator::Delimiter(
+                delimiter.chars().collect(),
+            )));
+        }
+
+        self.record_regex
+            .as_deref()
+            .map(|regex| {
+                let regex = Regex::new(regex)
+                    .with_context(|| format!("invalid record regex \"{regex}\""))?;
+                Ok(RecordSeparator::Regex(regex))
+            })
+            .transpose()
+    }
+}
+
+/// What separates records in the input.
+///
+/// Records are the non-empty parts of the input between separators, so separators
+/// at the start and at the end, or repeated ones, don't make empty records.
+#[derive(Debug, Clone)]
+pub(crate) enum RecordSeparator {
+    Delimiter(Vec<char>),
+    /// One or more lines with only whitespace.
+    Paragraph,
+    /// Whole lines that match the regex, without the line break.
+    Regex(Regex),
+}
+
+fn unescape(text: &str) -> Result<String> {
+    let mut result = String::new();
+    let mut chars = text.chars();
+
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            result.push(char);
+            continue;
+        }
+
+        match chars.next() {
+            Some('0') => result.push('\0'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('\\') => result.push('\\'),
+            Some('x') => {
+                let digits = chars.as_str().get(..2).unwrap_or_default();
+                let code = u8::from_str_radix(digits, 16)
+                    .ok()
+                    .filter(u8::is_ascii)
+                    .ok_or_else(|| anyhow!("expected two hex digits up to 7f after \\x"))?;
+                result.push(char::from(code));
+                chars.nth(1);
+            }
+            Some(char) => bail!("unknown escape \\{char}"),
+            None => bail!("dangling \\ at the end"),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Counts records and the words and characters inside them, separators are not counted.
+///
+/// Characters are pushed one by one. Only the current line is kept in memory when
+/// records are separated by lines, and only a partial match of the delimiter otherwise.
+#[derive(Debug)]
+pub(crate) struct RecordCounter {
+    separator: RecordSeparator,
+    /// Beginning of the delimiter seen so far, or the current line, not counted yet.
+    pending: Vec<char>,
+    in_record: bool,
+    in_word: bool,
+    pub(crate) records: usize,
+    pub(crate) words: usize,
+    pub(crate) chars: usize,
+}
+
+impl RecordCounter {
+    pub(crate) fn new(separator: RecordSeparator) -> Self {
+        Self {
+            separator,
+            pending: Vec::new(),
+            in_record: false,
+            in_word: false,
+            records: 0,
+            words: 0,
+            chars: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, char: char) {
+        self.pending.push(char);
+
+        if let RecordSeparator::Delimiter(delimiter) = &self.separator {
+            if self.pending == *delimiter {
+                self.pending.clear();
+                self.end_record();
+                return;
+            }
+
+            // the delimiter can start later in the pending characters
+            let mut start = 0;
+            while !delimiter.starts_with(&self.pending[start..]) {
+                start += 1;
+            }
+            let content = self.pending.drain(..start).collect::<Vec<_>>();
+            content.into_iter().for_each(|char| self.content(char));
+        } else if char == '\n' {
+            self.line();
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        match self.separator {
+            RecordSeparator::Delimiter(_) => {
+                let content = std::mem::take(&mut self.pending);
+                content.into_iter().for_each(|char| self.content(char));
+            }
+            RecordSeparator::Paragraph | RecordSeparator::Regex(_) => self.line(),
+        }
+        self.end_record();
+    }
+
+    fn line(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let line = std::mem::take(&mut self.pending);
+        let is_separator = match &self.separator {
+            RecordSeparator::Delimiter(_) => unreachable!("delimiters are matched by characters"),
+            RecordSeparator::Paragraph => line.iter().all(|char| char.is_whitespace()),
+            RecordSeparator::Regex(regex) => {
+                let text = line.iter().collect::<String>();
+                regex.is_match(text.strip_suffix('\n').unwrap_or(&text))
+            }
+        };
+
+        if is_separator {
+            self.end_record();
+        } else {
+            line.into_iter().for_each(|char| self.content(char));
+        }
+    }
+
+    fn content(&mut self, char: char) {
+        self.in_record = true;
+        self.chars += 1;
+
+        if char.is_whitespace() {
+            self.in_word = false;
+        } else if !self.in_word {
+            self.in_word = true;
+            self.words += 1;
+        }
+    }
+
+    fn end_record(&mut self) {
+        if self.in_record {
+            self.records += 1;
+        }
+        self.in_record = false;
+        self.in_word = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(separator: &RecordSeparator, text: &str) -> (usize, usize, usize) {
+        let mut counter = RecordCounter::new(separator.clone());
+        text.chars().for_each(|char| counter.push(char));
+        counter.finish();
+
+        (counter.records, counter.words, counter.chars)
+    }
+
+    fn separator(args: &[&str]) -> RecordSeparator {
+        let options = RecordOptions::parse_from(std::iter::once("t01").chain(args.iter().copied()));
+        options
+            .separator()
+            .expect("valid options")
+            .expect("separator is given")
+    }
+
+    #[test]
+    fn test_delimiter() {
+        let nul = separator(&["--record-delimiter", r"\0"]);
+        assert_eq!(count(&nul, ""), (0, 0, 0));
+        assert_eq!(count(&nul, "a b\0c\0"), (2, 3, 4));
+        assert_eq!(count(&nul, "\0a\0\0b c"), (2, 3, 4));
+
+        // a partial match of the delimiter is a part of the record
+        let dashes = separator(&["--record-delimiter", "--\\x1e"]);
+        assert_eq!(count(&dashes, "a - b ---\u{1e}c\u{1e}--\u{1e}"), (2, 5, 9));
+        assert_eq!(count(&dashes, "---\u{1e}"), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_paragraph() {
+        let paragraph = separator(&["--record-delimiter", ""]);
+        assert_eq!(
+            count(&paragraph, "\n \nfirst line\nsecond\n\t\n\nnext"),
+            (2, 4, 22)
+        );
+        assert_eq!(count(&paragraph, "no trailing newline"), (1, 3, 19));
+    }
+
+    #[test]
+    fn test_regex() {
+        let regex = separator(&["--record-regex", "^---$"]);
+        assert_eq!(
+            count(&regex, "---\na: 1\n---\nb: 2\n\nc: 3\n--- \n"),
+            (2, 7, 21)
+        );
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let error = |args: &[&str]| {
+            let options =
+                RecordOptions::parse_from(std::iter::once("t01").chain(args.iter().copied()));
+            format!("{:#}", options.separator().expect_err("invalid options"))
+        };
+
+        assert_eq!(
+            error(&["--record-delimiter", r"\q"]),
+            r#"invalid record delimiter "\q": unknown escape \q"#
+        );
+        assert_eq!(
+            error(&["--record-delimiter", r"\x8"]),
+            r#"invalid record delimiter "\x8": expected two hex digits up to 7f after \x"#
+        );
+        assert!(error(&["--record-regex", "("]).starts_with(r#"invalid record regex "(""#));
+    }
+}
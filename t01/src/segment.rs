@@ -0,0 +1,112 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Counts words by Unicode word boundaries (UAX #29) and extended grapheme clusters.
+///
+/// Both kinds of boundaries always break after a line feed, so the input is segmented
+/// line by line: only the current line is kept in memory.
+#[derive(Debug, Default)]
+pub(crate) struct LineSegmenter {
+    /// Characters of the current line that are not segmented yet.
+    line: String,
+    /// The segmenter started in the middle of a line: the beginning of the line is unknown,
+    /// so the text before the first line feed is kept until [`LineSegmenter::absorb`].
+    started_mid_line: bool,
+    head: Option<String>,
+    pub(crate) words: usize,
+    pub(crate) graphemes: usize,
+}
+
+impl LineSegmenter {
+    pub(crate) fn continuation() -> Self {
+        Self {
+            started_mid_line: true,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, char: char) {
+        self.line.push(char);
+
+        if char == '\n' {
+            if self.started_mid_line && self.head.is_none() {
+                self.head = Some(std::mem::take(&mut self.line));
+            } else {
+                self.segment_line();
+            }
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.segment_line();
+    }
+
+    /// Continues with the segmenter of the part of the input that immediately follows.
+    pub(crate) fn absorb(&mut self, next: LineSegmenter) {
+        match next.head {
+            Some(head) => {
+                self.line.push_str(&head);
+                self.segment_line();
+                self.line = next.line;
+            }
+            None => self.line.push_str(&next.line),
+        }
+
+        self.words += next.words;
+        self.graphemes += next.graphemes;
+    }
+
+    fn segment_line(&mut self) {
+        self.words += self.line.unicode_words().count();
+        self.graphemes += self.line.graphemes(true).count();
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(text: &str) -> (usize, usize) {
+        let mut segmenter = LineSegmenter::default();
+        text.chars().for_each(|char| segmenter.push(char));
+        segmenter.finish();
+
+        (segmenter.words, segmenter.graphemes)
+    }
+
+    #[test]
+    fn test_segment() {
+        assert_eq!(segment(""), (0, 0));
+        assert_eq!(
+            segment("The quick (\"brown\") fox can't jump 32.3 feet, right?"),
+            (9, 52)
+        );
+        // every ideograph is a word without a dictionary
+        assert_eq!(segment("日本語のテキスト"), (5, 8));
+        // `e` with a combining acute accent is a single grapheme
+        assert_eq!(segment("cafe\u{301}\r\n"), (1, 5));
+        assert_eq!(segment("👨‍👩‍👧 family\n🇺🇦"), (1, 10));
+    }
+
+    #[test]
+    fn test_absorb() {
+        let text = "first line\nsecond line that is split\nthird\u{301} line\n";
+
+        for split in (0..=text.len()).filter(|&split| text.is_char_boundary(split)) {
+            let (head, tail) = text.split_at(split);
+
+            let mut segmenter = LineSegmenter::default();
+            head.chars().for_each(|char| segmenter.push(char));
+            let mut continuation = LineSegmenter::continuation();
+            tail.chars().for_each(|char| continuation.push(char));
+            segmenter.absorb(continuation);
+            segmenter.finish();
+
+            assert_eq!(
+                (segmenter.words, segmenter.graphemes),
+                segment(text),
+                "split at {split}"
+            );
+        }
+    }
+}
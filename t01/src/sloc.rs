@@ -0,0 +1,828 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead},
+};
+
+use camino::Utf8Path;
+use serde::Serialize;
+
+use crate::{
+    counter::{CountOptions, Counter},
+    output::{Format, InputError},
+};
+
+/// String literal syntax, comment markers inside strings are code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Quote {
+    open: &'static str,
+    close: &'static str,
+    /// Whether a backslash escapes the next character.
+    escapes: bool,
+}
+
+const fn quote(open: &'static str, close: &'static str) -> Quote {
+    Quote {
+        open,
+        close,
+        escapes: true,
+    }
+}
+
+const fn raw_quote(open: &'static str, close: &'static str) -> Quote {
+    Quote {
+        open,
+        close,
+        escapes: false,
+    }
+}
+
+const C_QUOTES: &[Quote] = &[quote("\"", "\""), quote("'", "'")];
+
+const JS_QUOTES: &[Quote] = &[quote("\"", "\""), quote("'", "'"), quote("`", "`")];
+
+const C_BLOCK_COMMENTS: &[(&str, &str)] = &[("/*", "*/")];
+
+/// Comment and string syntax of a programming language.
+#[derive(Debug)]
+pub(crate) struct Language {
+    pub(crate) name: &'static str,
+    extensions: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+    /// Whether block comments can be nested, like `/* /* */ */` in Rust.
+    nested_comments: bool,
+    /// Longer delimiters go first, so `"""` is not taken for an empty string.
+    quotes: &'static [Quote],
+    /// Whether `'` starts a character literal or a lifetime, like in Rust, not a string.
+    char_literals: bool,
+    /// Whether a triple-quoted string first on its line is a docstring, like in Python,
+    /// counted as a comment.
+    docstrings: bool,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        name: "C",
+        extensions: &["c", "h"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: C_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "C#",
+        extensions: &["cs"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: C_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "C++",
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: C_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "CSS",
+        extensions: &["css"],
+        line_comments: &[],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: C_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Go",
+        extensions: &["go"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: &[quote("\"", "\""), quote("'", "'"), raw_quote("`", "`")],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "HTML",
+        extensions: &["htm", "html"],
+        line_comments: &[],
+        block_comments: &[("<!--", "-->")],
+        nested_comments: false,
+        quotes: &[],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Haskell",
+        extensions: &["hs"],
+        line_comments: &["--"],
+        block_comments: &[("{-", "-}")],
+        nested_comments: true,
+        quotes: &[quote("\"", "\"")],
+        char_literals: true,
+        docstrings: false,
+    },
+    Language {
+        name: "Java",
+        extensions: &["java"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: &[
+            quote("\"\"\"", "\"\"\""),
+            quote("\"", "\""),
+            quote("'", "'"),
+        ],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "JavaScript",
+        extensions: &["cjs", "js", "jsx", "mjs"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: JS_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Kotlin",
+        extensions: &["kt", "kts"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: true,
+        quotes: &[
+            raw_quote("\"\"\"", "\"\"\""),
+            quote("\"", "\""),
+            quote("'", "'"),
+        ],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Lua",
+        extensions: &["lua"],
+        line_comments: &["--"],
+        block_comments: &[("--[[", "]]")],
+        nested_comments: false,
+        quotes: &[raw_quote("[[", "]]"), quote("\"", "\""), quote("'", "'")],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Python",
+        extensions: &["py", "pyi"],
+        line_comments: &["#"],
+        block_comments: &[],
+        nested_comments: false,
+        quotes: &[
+            quote("\"\"\"", "\"\"\""),
+            quote("'''", "'''"),
+            quote("\"", "\""),
+            quote("'", "'"),
+        ],
+        char_literals: false,
+        docstrings: true,
+    },
+    Language {
+        name: "Ruby",
+        extensions: &["rb"],
+        line_comments: &["#"],
+        block_comments: &[("=begin", "=end")],
+        nested_comments: false,
+        quotes: C_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Rust",
+        extensions: &["rs"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: true,
+        quotes: &[
+            raw_quote("r###\"", "\"###"),
+            raw_quote("r##\"", "\"##"),
+            raw_quote("r#\"", "\"#"),
+            raw_quote("r\"", "\""),
+            quote("\"", "\""),
+        ],
+        char_literals: true,
+        docstrings: false,
+    },
+    Language {
+        name: "SQL",
+        extensions: &["sql"],
+        line_comments: &["--"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: &[quote("'", "'")],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Shell",
+        extensions: &["bash", "sh", "zsh"],
+        line_comments: &["#"],
+        block_comments: &[],
+        nested_comments: false,
+        quotes: &[quote("\"", "\""), raw_quote("'", "'")],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "Swift",
+        extensions: &["swift"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: true,
+        quotes: &[quote("\"\"\"", "\"\"\""), quote("\"", "\"")],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "TOML",
+        extensions: &["toml"],
+        line_comments: &["#"],
+        block_comments: &[],
+        nested_comments: false,
+        quotes: &[
+            quote("\"\"\"", "\"\"\""),
+            raw_quote("'''", "'''"),
+            quote("\"", "\""),
+            raw_quote("'", "'"),
+        ],
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "TypeScript",
+        extensions: &["cts", "mts", "ts", "tsx"],
+        line_comments: &["//"],
+        block_comments: C_BLOCK_COMMENTS,
+        nested_comments: false,
+        quotes: JS_QUOTES,
+        char_literals: false,
+        docstrings: false,
+    },
+    Language {
+        name: "YAML",
+        extensions: &["yaml", "yml"],
+        line_comments: &["#"],
+        block_comments: &[],
+        nested_comments: false,
+        quotes: &[quote("\"", "\""), raw_quote("'", "'")],
+        char_literals: false,
+        docstrings: false,
+    },
+];
+
+impl Language {
+    /// Detects the language by the file extension.
+    pub(crate) fn from_path(path: &Utf8Path) -> Option<&'static Language> {
+        let extension = path.extension()?.to_lowercase();
+
+        LANGUAGES
+            .iter()
+            .find(|language| language.extensions.contains(&extension.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Code,
+    Comment,
+    Blank,
+}
+
+/// Classifies lines of a source file one by one, keeping the open comment or string between lines.
+#[derive(Debug)]
+struct Classifier {
+    language: &'static Language,
+    /// Delimiters and nesting depth of the open block comment.
+    comment: Option<((&'static str, &'static str), usize)>,
+    string: Option<Quote>,
+    /// Whether the open string is a docstring.
+    docstring: bool,
+}
+
+impl Classifier {
+    fn new(language: &'static Language) -> Self {
+        Self {
+            language,
+            comment: None,
+            string: None,
+            docstring: false,
+        }
+    }
+
+    /// A line with any code is a code line, even if it has a comment too.
+    fn classify(&mut self, line: &str) -> LineKind {
+        if line.trim().is_empty() {
+            return match self.string {
+                Some(_) if self.docstring => LineKind::Comment,
+                Some(_) => LineKind::Code,
+                None => LineKind::Blank,
+            };
+        }
+
+        let language = self.language;
+        let mut has_code = false;
+        let mut has_comment = self.comment.is_some();
+        let mut rest = line;
+
+        while let Some(char) = rest.chars().next() {
+            if let Some(((open, close), depth)) = &mut self.comment {
+                if let Some(after) = rest.strip_prefix(*close) {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        self.comment = None;
+                    }
+                    rest = after;
+                } else if let Some(after) = rest
+                    .strip_prefix(*open)
+                    .filter(|_| language.nested_comments)
+                {
+                    *depth += 1;
+                    rest = after;
+                } else {
+                    rest = &rest[char.len_utf8()..];
+                }
+                continue;
+            }
+
+            if let Some(quote) = self.string {
+                if self.docstring {
+                    has_comment = true;
+                } else {
+                    has_code = true;
+                }
+                if quote.escapes && char == '\\' {
+                    rest = skip_chars(rest, 2);
+                } else if let Some(after) = rest.strip_prefix(quote.close) {
+                    self.string = None;
+                    rest = after;
+                } else {
+                    rest = &rest[char.len_utf8()..];
+                }
+                continue;
+            }
+
+            if char.is_whitespace() {
+                rest = &rest[char.len_utf8()..];
+                continue;
+            }
+
+            // block comments go first, so `--[[` in Lua is not taken for a line comment
+            if let Some(&(open, close)) = language
+                .block_comments
+                .iter()
+                .find(|(open, _)| rest.starts_with(open))
+            {
+                self.comment = Some(((open, close), 1));
+                has_comment = true;
+                rest = &rest[open.len()..];
+                continue;
+            }
+
+            if language
+                .line_comments
+                .iter()
+                .any(|marker| rest.starts_with(marker))
+            {
+                has_comment = true;
+                break;
+            }
+
+            if let Some(quote) = language
+                .quotes
+                .iter()
+                .find(|quote| rest.starts_with(quote.open))
+            {
+                self.docstring = language.docstrings && !has_code && quote.open.len() == 3;
+                if self.docstring {
+                    has_comment = true;
+                } else {
+                    has_code = true;
+                }
+                self.string = Some(*quote);
+                rest = &rest[quote.open.len()..];
+                continue;
+            }
+
+            has_code = true;
+            if language.char_literals && char == '\'' {
+                rest = skip_char_literal(rest);
+            } else {
+                rest = &rest[char.len_utf8()..];
+            }
+        }
+
+        match (has_code, has_comment) {
+            (true, _) => LineKind::Code,
+            (false, true) => LineKind::Comment,
+            (false, false) => LineKind::Blank,
+        }
+    }
+}
+
+fn skip_chars(text: &str, count: usize) -> &str {
+    let mut chars = text.chars();
+    for _ in 0..count {
+        chars.next();
+    }
+
+    chars.as_str()
+}
+
+/// Skips a character literal like `'"'` or `'\''`, or only the quote of a lifetime like `'a`.
+fn skip_char_literal(text: &str) -> &str {
+    let literal = text.strip_prefix('\'').unwrap_or(text);
+    if let Some(escaped) = literal.strip_prefix('\\') {
+        if let Some(end) = escaped.get(1..).and_then(|rest| rest.find('\'')) {
+            return &escaped[end + 2..];
+        }
+    }
+
+    let after_char = skip_chars(literal, 1);
+    after_char.strip_prefix('\'').unwrap_or(literal)
+}
+
+/// Numbers of lines of each kind in source files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct SlocCounts {
+    pub(crate) files: usize,
+    pub(crate) lines: usize,
+    pub(crate) code: usize,
+    pub(crate) comments: usize,
+    pub(crate) blanks: usize,
+}
+
+impl SlocCounts {
+    fn add(&mut self, other: &SlocCounts) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.code += other.code;
+        self.comments += other.comments;
+        self.blanks += other.blanks;
+    }
+}
+
+/// Counts code, comment and blank lines of a single file.
+///
+/// Lines are split by the same [`Counter`] that counts them for `-l`: a last line without
+/// a line feed is counted too. Bytes that are not valid UTF-8 are replaced.
+pub(crate) fn count_reader(
+    mut reader: impl BufRead,
+    language: &'static Language,
+) -> io::Result<SlocCounts> {
+    let mut classifier = Classifier::new(language);
+    let mut counts = SlocCounts {
+        files: 1,
+        ..SlocCounts::default()
+    };
+    let mut counter = Counter::new(&CountOptions {
+        keep_lines: true,
+        ..CountOptions::default()
+    });
+    let mut classify = |line: &str| match classifier.classify(line) {
+        LineKind::Code => counts.code += 1,
+        LineKind::Comment => counts.comments += 1,
+        LineKind::Blank => counts.blanks += 1,
+    };
+
+    loop {
+        let chunk = match reader.fill_buf() {
+            Ok([]) => break,
+            Ok(chunk) => chunk,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        counter.update(chunk);
+        let read = chunk.len();
+        reader.consume(read);
+
+        for line in counter.take_lines() {
+            classify(&line);
+        }
+    }
+    if let Some(line) = counter.take_last_line() {
+        classify(&line);
+    }
+
+    counts.lines = counter.finish().lines;
+
+    Ok(counts)
+}
+
+/// Line counts summed up per language.
+#[derive(Debug, Default)]
+pub(crate) struct SlocReport {
+    pub(crate) languages: BTreeMap<&'static str, SlocCounts>,
+    pub(crate) errors: Vec<InputError>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonLanguage<'report> {
+    name: &'report str,
+    #[serde(flatten)]
+    counts: SlocCounts,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonError<'report> {
+    name: Option<&'report str>,
+    error: &'report str,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonReport<'report> {
+    languages: Vec<JsonLanguage<'report>>,
+    total: SlocCounts,
+    errors: Vec<JsonError<'report>>,
+}
+
+const HEADER: [&str; 6] = ["Language", "Files", "Lines", "Code", "Comments", "Blanks"];
+
+impl SlocReport {
+    pub(crate) fn add(&mut self, language: &'static Language, counts: &SlocCounts) {
+        self.languages.entry(language.name).or_default().add(counts);
+    }
+
+    pub(crate) fn total(&self) -> SlocCounts {
+        let mut total = SlocCounts::default();
+        for counts in self.languages.values() {
+            total.add(counts);
+        }
+
+        total
+    }
+
+    pub(crate) fn format(&self, format: Format) -> String {
+        match format {
+            Format::Plain => self.format_plain(),
+            Format::Json => self.format_json(),
+            Format::Csv => self.format_csv(),
+        }
+    }
+
+    fn rows(&self) -> Vec<[String; 6]> {
+        let total = self.total();
+        self.languages
+            .iter()
+            .map(|(name, counts)| (*name, counts))
+            .chain([("Total", &total)])
+            .map(|(name, counts)| {
+                [
+                    name.to_owned(),
+                    counts.files.to_string(),
+                    counts.lines.to_string(),
+                    counts.code.to_string(),
+                    counts.comments.to_string(),
+                    counts.blanks.to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    /// A table like `tokei` prints: the language name is left-aligned, numbers are right-aligned.
+    fn format_plain(&self) -> String {
+        let header = HEADER.map(ToOwned::to_owned);
+        let rows = self.rows();
+
+        let mut widths = [0; 6];
+        for row in std::iter::once(&header).chain(&rows) {
+            for (width, column) in widths.iter_mut().zip(row) {
+                *width = (*width).max(column.chars().count());
+            }
+        }
+
+        std::iter::once(&header)
+            .chain(&rows)
+            .map(|row| {
+                let mut line = format!("{:<width$}", row[0], width = widths[0]);
+                for (column, width) in row.iter().zip(widths).skip(1) {
+                    line.push_str(&format!(" {column:>width$}"));
+                }
+
+                line + "\n"
+            })
+            .collect()
+    }
+
+    fn format_json(&self) -> String {
+        let report = JsonReport {
+            languages: self
+                .languages
+                .iter()
+                .map(|(name, counts)| JsonLanguage {
+                    name,
+                    counts: *counts,
+                })
+                .collect(),
+            total: self.total(),
+            errors: self
+                .errors
+                .iter()
+                .map(|error| JsonError {
+                    name: error.name.as_deref(),
+                    error: &error.message,
+                })
+                .collect(),
+        };
+
+        serde_json::to_string_pretty(&report).expect("report is serializable") + "\n"
+    }
+
+    fn format_csv(&self) -> String {
+        std::iter::once(HEADER.map(str::to_lowercase))
+            .chain(self.rows())
+            .map(|row| row.join(",") + "\n")
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use super::*;
+
+    fn assert_classified(extension: &str, source: &str, expected: &Expect) {
+        let language = Language::from_path(Utf8Path::new(&format!("file.{extension}")))
+            .expect("known language");
+        let mut classifier = Classifier::new(language);
+
+        let actual = source
+            .lines()
+            .map(|line| {
+                let kind = format!("{:?}", classifier.classify(line));
+                format!("{kind:<7} | {line}").trim_end().to_owned()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_rust() {
+        assert_classified(
+            "rs",
+            r##"
+//! Module documentation
+use std::fmt; // trailing comment
+
+/* block
+   /* nested */
+   still comment */
+fn main() {
+    let url = "http://example.com/*not a comment*/";
+    let raw = r#"// not a comment "# ;
+    let quote = '"'; let escaped = '\''; // comment
+    let multiline = "first
+// inside string
+
+still string";
+    /* one */ let after_comment = 1;
+}
+
+fn lifetime<'a>(text: &'a str) -> &'a str { text } // "quoted"
+"##,
+            &expect![[r##"
+                Blank   |
+                Comment | //! Module documentation
+                Code    | use std::fmt; // trailing comment
+                Blank   |
+                Comment | /* block
+                Comment |    /* nested */
+                Comment |    still comment */
+                Code    | fn main() {
+                Code    |     let url = "http://example.com/*not a comment*/";
+                Code    |     let raw = r#"// not a comment "# ;
+                Code    |     let quote = '"'; let escaped = '\''; // comment
+                Code    |     let multiline = "first
+                Code    | // inside string
+                Code    |
+                Code    | still string";
+                Code    |     /* one */ let after_comment = 1;
+                Code    | }
+                Blank   |
+                Code    | fn lifetime<'a>(text: &'a str) -> &'a str { text } // "quoted""##]],
+        );
+    }
+
+    #[test]
+    fn test_c_comments_are_not_nested() {
+        assert_classified(
+            "c",
+            "/* outer /* inner */\nint x;\n*/",
+            &expect![[r#"
+                Comment | /* outer /* inner */
+                Code    | int x;
+                Code    | */"#]],
+        );
+    }
+
+    #[test]
+    fn test_python() {
+        assert_classified(
+            "py",
+            r#"# comment
+text = '# not a comment'
+
+    # indented comment
+def f():
+    """Docstring
+
+    with a blank line.
+    """
+    """One line"""; x = 1
+    text = """not a
+docstring"""
+"#,
+            &expect![[r#"
+                Comment | # comment
+                Code    | text = '# not a comment'
+                Blank   |
+                Comment |     # indented comment
+                Code    | def f():
+                Comment |     """Docstring
+                Comment |
+                Comment |     with a blank line.
+                Comment |     """
+                Code    |     """One line"""; x = 1
+                Code    |     text = """not a
+                Code    | docstring""""#]],
+        );
+    }
+
+    #[test]
+    fn test_lines_match_line_count() {
+        let rust = Language::from_path(Utf8Path::new("lib.rs")).expect("Rust");
+
+        for source in [
+            &b""[..],
+            b"\n",
+            b"fn f() {}",
+            b"// comment\r\nfn f() {}\r\n\r\n",
+            b"fn f() {}\r// not a line of its own\n",
+            b"let bad = \"\xff\";\n\n// last",
+        ] {
+            let counts = count_reader(source, rust).expect("reading from slice");
+            let line_count = crate::counter::count_reader(source, &CountOptions::default())
+                .expect("reading from slice")
+                .lines;
+
+            assert_eq!(counts.lines, line_count, "{}", source.escape_ascii());
+            assert_eq!(
+                counts.code + counts.comments + counts.blanks,
+                line_count,
+                "{}",
+                source.escape_ascii()
+            );
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let rust = Language::from_path(Utf8Path::new("src/main.rs")).expect("Rust");
+        let python = Language::from_path(Utf8Path::new("script.PY")).expect("Python");
+        assert!(Language::from_path(Utf8Path::new("README")).is_none());
+
+        let mut report = SlocReport::default();
+        for (language, source) in [
+            (rust, "// comment\nfn main() {}\n\n"),
+            (rust, "fn f() {}"),
+            (python, "# comment\n"),
+        ] {
+            let counts = count_reader(source.as_bytes(), language).expect("reading from slice");
+            report.add(language, &counts);
+        }
+
+        expect![[r#"
+            Language Files Lines Code Comments Blanks
+            Python       1     1    0        1      0
+            Rust         2     4    2        1      1
+            Total        3     5    2        2      1
+        "#]]
+        .assert_eq(&report.format(Format::Plain));
+
+        expect![[r#"
+            language,files,lines,code,comments,blanks
+            Python,1,1,0,1,0
+            Rust,2,4,2,1,1
+            Total,3,5,2,2,1
+        "#]]
+        .assert_eq(&report.format(Format::Csv));
+    }
+}
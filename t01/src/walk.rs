@@ -0,0 +1,292 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+use crate::output::InputError;
+
+/// Options selecting the files to count.
+#[derive(Parser, Debug)]
+pub(crate) struct WalkOptions {
+    #[arg(short, long)]
+    /// Count all files in directories and their subdirectories, except hidden ones.
+    pub(crate) recursive: bool,
+
+    #[arg(long, value_name = "GLOB")]
+    /// Count only files in directories that match the glob, can be repeated.
+    /// The glob is matched against the path relative to the directory and against the file name.
+    pub(crate) include: Vec<String>,
+
+    #[arg(long, value_name = "GLOB")]
+    /// Skip files and directories that match the glob, can be repeated.
+    pub(crate) exclude: Vec<String>,
+
+    #[arg(long)]
+    /// Don't skip files ignored by `.gitignore` and `.ignore` files in directories.
+    pub(crate) no_ignore: bool,
+}
+
+/// Expands directories given as inputs into the files inside them.
+#[derive(Debug)]
+pub(crate) struct Walker {
+    recursive: bool,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    git_ignore: bool,
+}
+
+impl Walker {
+    pub(crate) fn new(options: &WalkOptions) -> Result<Self> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(&options.include)?)
+        };
+
+        Ok(Self {
+            recursive: options.recursive,
+            include,
+            exclude: build_glob_set(&options.exclude)?,
+            git_ignore: !options.no_ignore,
+        })
+    }
+
+    /// Paths of the files to count: files are kept as is, directories are walked
+    /// when the walk is recursive. Files found in a directory are sorted by path.
+    ///
+    /// `None` stands for the standard input read when no path is given.
+    pub(crate) fn inputs(
+        &self,
+        paths: Vec<Utf8PathBuf>,
+    ) -> Vec<Result<Option<Utf8PathBuf>, InputError>> {
+        if paths.is_empty() {
+            return vec![Ok(None)];
+        }
+
+        let mut inputs = Vec::new();
+        for path in paths {
+            if self.recursive && path.is_dir() {
+                inputs.extend(self.walk(&path).map(|path| path.map(Some)));
+            } else {
+                inputs.push(Ok(Some(path)));
+            }
+        }
+
+        inputs
+    }
+
+    fn walk(&self, root: &Utf8Path) -> impl Iterator<Item = Result<Utf8PathBuf, InputError>> {
+        let exclude = self.exclude.clone();
+        let exclude_root = root.to_owned();
+
+        let walk = WalkBuilder::new(root)
+            .git_ignore(self.git_ignore)
+            .git_exclude(self.git_ignore)
+            .ignore(self.git_ignore)
+            .parents(self.git_ignore)
+            .require_git(false)
+            .filter_entry(move |entry| !matches(&exclude, &exclude_root, entry.path()))
+            .build();
+
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    errors.push(walk_error(root, &err));
+                    continue;
+                }
+            };
+
+            if !entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+            {
+                continue;
+            }
+
+            let included = self
+                .include
+                .as_ref()
+                .is_none_or(|include| matches(include, root, entry.path()));
+            if !included {
+                continue;
+            }
+
+            match Utf8PathBuf::try_from(entry.into_path()) {
+                Ok(path) => files.push(path),
+                Err(err) => errors.push(InputError {
+                    name: Some(err.as_path().display().to_string()),
+                    message: "path is not valid UTF-8".to_owned(),
+                }),
+            }
+        }
+
+        files.sort();
+
+        files.into_iter().map(Ok).chain(errors.into_iter().map(Err))
+    }
+}
+
+/// Reads NUL-separated paths, as GNU `wc --files0-from` does.
+pub(crate) fn read_files0_from(path: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let content = if path == "-" {
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut content)
+            .context("standard input")?;
+        content
+    } else {
+        std::fs::read(path).with_context(|| path.to_string())?
+    };
+
+    parse_files0(&content).with_context(|| path.to_string())
+}
+
+fn parse_files0(content: &[u8]) -> Result<Vec<Utf8PathBuf>> {
+    let content = content.strip_suffix(b"\0").unwrap_or(content);
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    content
+        .split(|&byte| byte == b'\0')
+        .enumerate()
+        .map(|(index, name)| {
+            if name.is_empty() {
+                bail!("invalid zero-length file name at position {}", index + 1);
+            }
+            let name = std::str::from_utf8(name)
+                .map_err(|_| anyhow!("file name at position {} is not valid UTF-8", index + 1))?;
+
+            Ok(Utf8PathBuf::from(name))
+        })
+        .collect()
+}
+
+fn build_glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).with_context(|| format!("invalid glob \"{glob}\""))?);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Matches the path relative to the walked directory and the file name.
+fn matches(globs: &GlobSet, root: &Utf8Path, path: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    globs.is_match(relative) || path.file_name().is_some_and(|name| globs.is_match(name))
+}
+
+fn walk_error(root: &Utf8Path, err: &ignore::Error) -> InputError {
+    match err {
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error(root, err)
+        }
+        ignore::Error::WithPath { path, err } => InputError {
+            name: Some(path.display().to_string()),
+            message: err.to_string(),
+        },
+        err => InputError {
+            name: Some(root.to_string()),
+            message: err.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use super::*;
+
+    fn assert_walk(args: &[&str], expected: &Expect) {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let root = Utf8Path::from_path(dir.path()).expect("UTF-8 temporary directory");
+
+        for file in [
+            ".gitignore",
+            "README.md",
+            "src/main.rs",
+            "src/lib.rs",
+            "src/generated/mod.rs",
+            "target/debug/build.rs",
+            "tests/a.rs",
+            "tests/data.txt",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().expect("file in directory"))
+                .expect("creating directory");
+            std::fs::write(&path, "content").expect("writing file");
+        }
+        std::fs::write(root.join(".gitignore"), "target/\n").expect("writing .gitignore");
+
+        let options = WalkOptions::parse_from(std::iter::once("t01").chain(args.iter().copied()));
+        let walker = Walker::new(&options).expect("valid options");
+
+        let actual = walker
+            .inputs(vec![root.to_owned()])
+            .into_iter()
+            .map(|input| {
+                let path = input.expect("no errors in tests").expect("path is given");
+                path.strip_prefix(root)
+                    .expect("walked inside root")
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_walk() {
+        assert_walk(
+            &["-r"],
+            &expect![[r#"
+                README.md
+                src/generated/mod.rs
+                src/lib.rs
+                src/main.rs
+                tests/a.rs
+                tests/data.txt"#]],
+        );
+
+        assert_walk(
+            &["-r", "--include", "*.rs", "--exclude", "generated"],
+            &expect![[r#"
+                src/lib.rs
+                src/main.rs
+                tests/a.rs"#]],
+        );
+
+        assert_walk(
+            &["-r", "--no-ignore", "--include", "**/debug/*"],
+            &expect!["target/debug/build.rs"],
+        );
+    }
+
+    #[test]
+    fn test_not_recursive() {
+        let walker = Walker::new(&WalkOptions::parse_from(["t01"])).expect("valid options");
+        let inputs = walker.inputs(vec![Utf8PathBuf::from("src")]);
+
+        assert!(matches!(inputs.as_slice(), [Ok(Some(path))] if path == "src"));
+    }
+
+    #[test]
+    fn test_parse_files0() {
+        let paths = parse_files0(b"a.txt\0dir/b c.txt\0").expect("valid list");
+        assert_eq!(paths, ["a.txt", "dir/b c.txt"]);
+
+        let paths = parse_files0(b"").expect("valid list");
+        assert!(paths.is_empty());
+
+        let err = parse_files0(b"a.txt\0\0b.txt").expect_err("empty name");
+        expect!["invalid zero-length file name at position 2"].assert_eq(&err.to_string());
+    }
+}
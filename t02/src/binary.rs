@@ -0,0 +1,505 @@
+use std::{
+    fmt::{self, Write as _},
+    io::{self, Read, Write},
+};
+
+use itertools::Itertools;
+
+use crate::{grammar, streaming::Limits};
+
+/// Shortest run of equal bytes encoded as a repeat block, shorter runs are cheaper as literals.
+const MIN_RUN: usize = 3;
+
+/// Longest literal block the writer buffers before writing it out.
+const MAX_LITERAL: usize = 4096;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes an unsigned LEB128 varint: 7 bits per byte, the high bit marks that more bytes follow.
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+
+    writer.write_all(&bytes[..len])
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Reads a varint, `None` if the input ends before its first byte.
+fn read_varint(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    let mut value = 0_u64;
+
+    for shift in (0..64).step_by(7) {
+        let Some(byte) = read_byte(reader)? else {
+            return match shift {
+                0 => Ok(None),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        };
+
+        let bits = u64::from(byte & 0x7f);
+        if shift == 63 && bits > 1 {
+            return Err(invalid_data("varint doesn't fit in 64 bits"));
+        }
+        value |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(invalid_data("varint doesn't fit in 64 bits"))
+}
+
+/// Encodes the bytes written to it into the compact binary run-length format.
+///
+/// The format is a sequence of blocks, each starts with a varint header:
+/// an even header `2n` is followed by `n` literal bytes,
+/// an odd header `2n + 1` is followed by a single byte repeated `n + 3` times.
+///
+/// Runs can continue across writes, so the encoding is written out only by [`RleWriter::finish`].
+pub(crate) struct RleWriter<W: Write> {
+    inner: W,
+    literals: Vec<u8>,
+    /// The current run of equal bytes and its length.
+    run: Option<(u8, usize)>,
+}
+
+impl<W: Write> RleWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            literals: Vec::with_capacity(MAX_LITERAL),
+            run: None,
+        }
+    }
+
+    fn end_run(&mut self) -> io::Result<()> {
+        let Some((byte, count)) = self.run.take() else {
+            return Ok(());
+        };
+
+        if count >= MIN_RUN {
+            self.write_literals()?;
+            write_varint(&mut self.inner, ((count - MIN_RUN) as u64) << 1 | 1)?;
+            return self.inner.write_all(&[byte]);
+        }
+
+        for _ in 0..count {
+            self.literals.push(byte);
+            if self.literals.len() == MAX_LITERAL {
+                self.write_literals()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_literals(&mut self) -> io::Result<()> {
+        if self.literals.is_empty() {
+            return Ok(());
+        }
+
+        write_varint(&mut self.inner, (self.literals.len() as u64) << 1)?;
+        self.inner.write_all(&self.literals)?;
+        self.literals.clear();
+
+        Ok(())
+    }
+
+    /// Writes out the pending runs and returns the inner writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.end_run()?;
+        self.write_literals()?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for RleWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        for &byte in buffer {
+            match &mut self.run {
+                Some((run_byte, count)) if *run_byte == byte => *count += 1,
+                _ => {
+                    self.end_run()?;
+                    self.run = Some((byte, 1));
+                }
+            }
+        }
+
+        Ok(buffer.len())
+    }
+
+    /// Flushes only the inner writer, the current run may still continue.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The rest of the block being decoded.
+#[derive(Clone, Copy, Debug)]
+enum Block {
+    Literal { remaining: u64 },
+    Run { byte: u8, remaining: u64 },
+}
+
+/// Decodes the binary run-length format written by [`RleWriter`] while reading from it.
+///
+/// Headers are read byte by byte, so the inner reader should be buffered.
+pub(crate) struct RleReader<R: Read> {
+    inner: R,
+    block: Block,
+}
+
+impl<R: Read> RleReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: Block::Literal { remaining: 0 },
+        }
+    }
+}
+
+impl<R: Read> Read for RleReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match &mut self.block {
+                Block::Literal { remaining: 0 } | Block::Run { remaining: 0, .. } => {
+                    let Some(header) = read_varint(&mut self.inner)? else {
+                        return Ok(0);
+                    };
+
+                    self.block = if header & 1 == 0 {
+                        Block::Literal {
+                            remaining: header >> 1,
+                        }
+                    } else {
+                        let byte = read_byte(&mut self.inner)?
+                            .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                        Block::Run {
+                            byte,
+                            remaining: (header >> 1)
+                                .checked_add(MIN_RUN as u64)
+                                .ok_or(invalid_data("run doesn't fit in 64 bits"))?,
+                        }
+                    };
+                }
+                Block::Literal { remaining } => {
+                    let len = buffer
+                        .len()
+                        .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    let read = self.inner.read(&mut buffer[..len])?;
+                    if read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    *remaining -= read as u64;
+
+                    return Ok(read);
+                }
+                Block::Run { byte, remaining } => {
+                    let len = buffer
+                        .len()
+                        .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    buffer[..len].fill(*byte);
+                    *remaining -= len as u64;
+
+                    return Ok(len);
+                }
+            }
+        }
+    }
+}
+
+/// Encodes the bytes into the binary run-length format, see [`RleWriter`].
+pub fn encode(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = RleWriter::new(Vec::new());
+    writer.write_all(bytes).expect("writing to vector");
+    writer.finish().expect("writing to vector")
+}
+
+/// Decodes the binary run-length format, see [`RleReader`].
+///
+/// The output is not limited, untrusted input should go through [`decode_to_writer`].
+// the command line streams through `decode_to_writer`, this is for data already in memory
+#[allow(dead_code)]
+pub fn decode(encoded: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    RleReader::new(encoded).read_to_end(&mut decoded)?;
+
+    Ok(decoded)
+}
+
+/// Encodes everything read from the reader into the writer, returns the number of encoded bytes.
+pub(crate) fn encode_to_writer(mut reader: impl Read, writer: impl Write) -> io::Result<u64> {
+    let mut encoder = RleWriter::new(writer);
+    let read = io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+
+    Ok(read)
+}
+
+/// Decodes the binary run-length format from the reader into the writer,
+/// returns the number of decoded bytes.
+///
+/// Only the output limit applies, the output up to the limit is written before the error.
+pub(crate) fn decode_to_writer(
+    reader: impl Read,
+    limits: Limits,
+    mut writer: impl Write,
+) -> io::Result<u64> {
+    let limit = limits.max_output as u64;
+    let mut decoder = RleReader::new(reader);
+    let written = io::copy(&mut (&mut decoder).take(limit), &mut writer)?;
+    writer.flush()?;
+
+    if written == limit && read_byte(&mut decoder)?.is_some() {
+        return Err(invalid_data(&format!(
+            "decoded data is larger than {} bytes",
+            limits.max_output
+        )));
+    }
+
+    Ok(written)
+}
+
+/// Encodes the bytes into the textual form of the extended grammar:
+/// printable ASCII is kept, other bytes are written as `\n`, `\t`, `\r` or `\u{XX}` escapes.
+pub(crate) fn encode_text(bytes: &[u8]) -> String {
+    let mut text = String::new();
+
+    for (byte, run) in &bytes.iter().chunk_by(|&&byte| byte) {
+        match byte {
+            b'\n' => text.push_str(r"\n"),
+            b'\t' => text.push_str(r"\t"),
+            b'\r' => text.push_str(r"\r"),
+            b'0'..=b'9' | b'\\' | b'(' | b')' => {
+                text.push('\\');
+                text.push(char::from(byte));
+            }
+            byte if byte == b' ' || byte.is_ascii_graphic() => text.push(char::from(byte)),
+            byte => write!(text, "\\u{{{byte:x}}}").expect("writing to string"),
+        }
+
+        match run.count() {
+            1 => {}
+            count => text.push_str(&count.to_string()),
+        }
+    }
+
+    text
+}
+
+/// Decodes the textual form, every unpacked character must be a byte.
+pub(crate) fn decode_text(text: &str, limits: Limits) -> io::Result<Vec<u8>> {
+    grammar::unpack_extended(text, limits)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+        .chars()
+        .map(|char| {
+            u8::try_from(char)
+                .map_err(|_| invalid_data(&format!("character {char:?} is not a byte")))
+        })
+        .collect()
+}
+
+/// Sizes of the input encoded in the binary and in the textual forms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RatioReport {
+    pub(crate) input: usize,
+    pub(crate) binary: usize,
+    pub(crate) text: usize,
+}
+
+impl RatioReport {
+    pub(crate) fn new(bytes: &[u8]) -> Self {
+        Self {
+            input: bytes.len(),
+            binary: encode(bytes).len(),
+            text: encode_text(bytes).len(),
+        }
+    }
+
+    /// Size relative to the input in percent, an empty input is not compressed at all.
+    fn percent(&self, size: usize) -> f64 {
+        match self.input {
+            0 => 100.0,
+            input => size as f64 * 100.0 / input as f64,
+        }
+    }
+}
+
+impl fmt::Display for RatioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes, binary {} bytes ({:.1}%), text {} bytes ({:.1}%)",
+            self.input,
+            self.binary,
+            self.percent(self.binary),
+            self.text,
+            self.percent(self.text)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const UNLIMITED: Limits = Limits {
+        max_output: usize::MAX,
+        max_count: usize::MAX,
+    };
+
+    fn decode_limited(encoded: &[u8], limits: Limits) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        decode_to_writer(encoded, limits, &mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn binary_format() {
+        assert_eq!(encode(b""), b"");
+        assert_eq!(encode(b"ab"), b"\x04ab");
+        assert_eq!(encode(b"abbbc"), b"\x02a\x01b\x02c");
+        // 200 - 3 = 197, the header 395 takes two bytes
+        assert_eq!(encode(&[0; 200]), b"\x8b\x03\x00");
+
+        let literals = (0..=255).cycle().take(MAX_LITERAL + 1).collect::<Vec<u8>>();
+        assert_eq!(encode(&literals).len(), literals.len() + 2 + 1);
+    }
+
+    #[test]
+    fn encode_decode() {
+        for bytes in [&b""[..], b"a", b"abbbc", &[0; 200], b"\x00\xff\xff\xff\x80"] {
+            assert_eq!(decode(&encode(bytes)).expect("encoded is valid"), bytes);
+        }
+
+        let error = decode(b"\x06ab").expect_err("truncated literal");
+        expect!["unexpected end of file"].assert_eq(&error.to_string());
+    }
+
+    #[test]
+    fn invalid_binary() {
+        for (encoded, expected) in [
+            (&b"\x06ab"[..], expect!["unexpected end of file"]),
+            (b"\x85", expect!["unexpected end of file"]),
+            (b"\x05", expect!["unexpected end of file"]),
+            (
+                b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f",
+                expect!["varint doesn't fit in 64 bits"],
+            ),
+        ] {
+            let error = decode_limited(encoded, UNLIMITED).expect_err("invalid data");
+            expected.assert_eq(&error.to_string());
+        }
+
+        let limits = Limits {
+            max_output: 10,
+            ..UNLIMITED
+        };
+        assert_eq!(
+            decode_limited(b"\x0fa", limits).expect("10 bytes"),
+            [b'a'; 10]
+        );
+        let error = decode_limited(b"\x11a", limits).expect_err("11 bytes");
+        expect!["decoded data is larger than 10 bytes"].assert_eq(&error.to_string());
+    }
+
+    #[test]
+    fn text_form() {
+        let text = encode_text(b"aaab\n\n\x00\xff\xff1(  )");
+        expect![[r"a3b\n2\u{0}\u{ff}2\1\( 2\)"]].assert_eq(&text);
+        assert_eq!(
+            decode_text(&text, UNLIMITED).expect("valid text"),
+            b"aaab\n\n\x00\xff\xff1(  )"
+        );
+
+        let error = decode_text(r"a\u{100}", UNLIMITED).expect_err("not a byte");
+        expect!["character 'Ā' is not a byte"].assert_eq(&error.to_string());
+    }
+
+    #[test]
+    fn ratio_report() {
+        let report = RatioReport::new(&[b"header\n".as_slice(), &[0; 1000]].concat());
+        expect!["1007 bytes, binary 11 bytes (1.1%), text 17 bytes (1.7%)"]
+            .assert_eq(&report.to_string());
+        expect!["0 bytes, binary 0 bytes (100.0%), text 0 bytes (100.0%)"]
+            .assert_eq(&RatioReport::new(b"").to_string());
+    }
+
+    /// Bytes with long runs, so both kinds of blocks are used.
+    fn runs() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec((any::<u8>(), 1..10_usize), 0..20).prop_map(|runs| {
+            runs.into_iter()
+                .flat_map(|(byte, count)| vec![byte; count])
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(bytes in prop_oneof![any::<Vec<u8>>(), runs()]) {
+            let encoded = encode(&bytes);
+            prop_assert_eq!(&decode(&encoded).expect("encoded is valid"), &bytes);
+
+            let text = encode_text(&bytes);
+            prop_assert_eq!(&decode_text(&text, UNLIMITED).expect("text is valid"), &bytes);
+        }
+
+        /// The encoding doesn't depend on how the input is split into writes and reads.
+        #[test]
+        fn streaming(bytes in runs(), chunk in 1..8_usize) {
+            let mut writer = RleWriter::new(Vec::new());
+            for part in bytes.chunks(chunk) {
+                writer.write_all(part).expect("writing to vector");
+                writer.flush().expect("writing to vector");
+            }
+            let encoded = writer.finish().expect("writing to vector");
+            prop_assert_eq!(&encoded, &encode(&bytes));
+
+            let mut reader = RleReader::new(encoded.as_slice());
+            let mut decoded = Vec::new();
+            let mut buffer = vec![0; chunk];
+            loop {
+                match reader.read(&mut buffer).expect("encoded is valid") {
+                    0 => break,
+                    read => decoded.extend_from_slice(&buffer[..read]),
+                }
+            }
+            prop_assert_eq!(decoded, bytes);
+        }
+    }
+}
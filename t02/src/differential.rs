@@ -0,0 +1,94 @@
+//! Differential tests: the two independent engines must agree on every input,
+//! both on the unpacked string and on the error.
+
+use proptest::prelude::*;
+
+use crate::{
+    error::UnpackError,
+    impl_with_iterators::unpack_string_with_iterators,
+    impl_with_states::unpack_string_with_state,
+    streaming::{unpack_chars, Limits},
+};
+
+/// The engines without limits allocate the whole output, so larger inputs are skipped.
+const LIMITS: Limits = Limits {
+    max_output: 1024 * 1024,
+    max_count: usize::MAX,
+};
+
+#[track_caller]
+fn assert_engines_agree(input: &str) {
+    let expected = unpack_chars(input, LIMITS).collect::<Result<String, _>>();
+    if matches!(expected, Err(UnpackError::OutputLimit { .. })) {
+        return;
+    }
+
+    assert_eq!(unpack_string_with_iterators(input), expected, "{input:?}");
+    assert_eq!(unpack_string_with_state(input), expected, "{input:?}");
+}
+
+/// The baseline engines reported a leading digit with different messages: "found number that
+/// doesn't repeating anything" and "found digit that doesn't repeating anything".
+#[test]
+fn leading_digit() {
+    for input in ["0", "9a", "01"] {
+        assert_engines_agree(input);
+    }
+}
+
+/// The baseline engines both panicked with "attempt to multiply with overflow" (or "add") on
+/// counts above `usize::MAX`, and wrapped around in release builds, instead of returning an error.
+#[test]
+fn overflowing_count() {
+    for input in [
+        // the overflow is reported at the digit that overflows, also after an escape
+        "a99999999999999999999",
+        "\\\\99999999999999999999",
+        "\\999999999999999999999",
+        "a18446744073709551616",
+    ] {
+        assert_engines_agree(input);
+    }
+}
+
+/// Hand-picked edge cases: escapes, zero counts, multi-digit counts and invalid inputs.
+#[test]
+fn edge_cases() {
+    for input in [
+        "",
+        "\\",
+        "\\\\",
+        "a0",
+        "a00",
+        "a01",
+        "\\0",
+        "\\00",
+        "a\\",
+        "a2\\",
+        "\\a",
+        "é\\é",
+        "a0000000000000000000000000001",
+    ] {
+        assert_engines_agree(input);
+    }
+}
+
+fn input() -> impl Strategy<Value = String> {
+    let char = prop_oneof![
+        4 => prop::char::range('0', '9'),
+        2 => Just('\\'),
+        3 => prop::sample::select(vec!['a', 'b', 'é', '🦀', '\t', ' ']),
+        1 => any::<char>(),
+    ];
+
+    prop::collection::vec(char, 0..24).prop_map(String::from_iter)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(4096))]
+
+    #[test]
+    fn engines_agree(input in input()) {
+        assert_engines_agree(&input);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{fmt, str::CharIndices};
+
+use unicode_width::UnicodeWidthChar;
+
+/// Position of a character in the packed string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Position {
+    /// Offset in bytes, for slicing the string.
+    pub(crate) byte: usize,
+    /// Offset in characters, for showing it to users.
+    pub(crate) char: usize,
+}
+
+/// Characters of a string together with their positions.
+#[derive(Clone, Debug)]
+pub(crate) struct CharPositions<'a> {
+    chars: CharIndices<'a>,
+    next_char: usize,
+}
+
+impl<'a> CharPositions<'a> {
+    pub(crate) fn new(string: &'a str) -> Self {
+        Self {
+            chars: string.char_indices(),
+            next_char: 0,
+        }
+    }
+}
+
+impl Iterator for CharPositions<'_> {
+    type Item = (Position, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (byte, char) = self.chars.next()?;
+        let position = Position {
+            byte,
+            char: self.next_char,
+        };
+        self.next_char += 1;
+
+        Some((position, char))
+    }
+}
+
+/// Why a packed string can't be unpacked, with the position of the offending character.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum UnpackError {
+    /// A number at the start of the string, there is no character to repeat.
+    LeadingDigit { position: Position },
+    /// A backslash at the end of the string.
+    DanglingEscape { position: Position },
+    /// A character after a backslash that can't be escaped.
+    InvalidEscape { char: char, position: Position },
+    /// A repeat count that doesn't fit in `usize`, the position is of the digit that overflows it.
+    CountOverflow { position: Position },
+    /// A repeat count larger than the limit, the position is of its first digit.
+    CountLimit { limit: usize, position: Position },
+    /// A run that makes the unpacked string larger than the limit in bytes.
+    OutputLimit { limit: usize, position: Position },
+    /// A `\u` escape without braces around 1 to 6 hex digits of a Unicode scalar value.
+    InvalidUnicodeEscape { position: Position },
+    /// An opening parenthesis without the closing one.
+    UnclosedGroup { position: Position },
+    /// A closing parenthesis without the opening one.
+    UnmatchedParen { position: Position },
+    /// An opening parenthesis nested deeper than the limit.
+    NestingLimit { limit: usize, position: Position },
+}
+
+impl UnpackError {
+    pub(crate) fn position(&self) -> Position {
+        match self {
+            UnpackError::LeadingDigit { position }
+            | UnpackError::DanglingEscape { position }
+            | UnpackError::InvalidEscape { position, .. }
+            | UnpackError::CountOverflow { position }
+            | UnpackError::CountLimit { position, .. }
+            | UnpackError::OutputLimit { position, .. }
+            | UnpackError::NestingLimit { position, .. }
+            | UnpackError::InvalidUnicodeEscape { position }
+            | UnpackError::UnclosedGroup { position }
+            | UnpackError::UnmatchedParen { position } => *position,
+        }
+    }
+
+    /// Renders the error followed by the input with a `^` under the offending character.
+    ///
+    /// The caret is aligned by the display width of the preceding characters,
+    /// tabs are kept as is so they expand the same way in both lines.
+    pub(crate) fn render(&self, input: &str) -> String {
+        let position = self.position();
+        let indent = input[..position.byte]
+            .chars()
+            .map(|char| match char {
+                '\t' => "\t".to_owned(),
+                char => " ".repeat(char.width().unwrap_or_default()),
+            })
+            .collect::<String>();
+
+        format!("error: {self}\n{input}\n{indent}^\n")
+    }
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnpackError::LeadingDigit { .. } => write!(f, "number doesn't repeat anything")?,
+            UnpackError::DanglingEscape { .. } => {
+                write!(f, "last escape character doesn't escape anything")?;
+            }
+            UnpackError::InvalidEscape { char, .. } => write!(f, "cannot escape '{char}'")?,
+            UnpackError::CountOverflow { .. } => write!(f, "repeat count is too large")?,
+            UnpackError::CountLimit { limit, .. } => {
+                write!(f, "repeat count is larger than {limit}")?;
+            }
+            UnpackError::OutputLimit { limit, .. } => {
+                write!(f, "unpacked string is larger than {limit} bytes")?;
+            }
+            UnpackError::InvalidUnicodeEscape { .. } => {
+                write!(f, "invalid unicode escape, expected \\u{{XXXX}}")?;
+            }
+            UnpackError::UnclosedGroup { .. } => write!(f, "group is never closed")?,
+            UnpackError::UnmatchedParen { .. } => write!(f, "unmatched closing parenthesis")?,
+            UnpackError::NestingLimit { limit, .. } => {
+                write!(f, "groups are nested deeper than {limit}")?;
+            }
+        }
+
+        write!(f, " at character {}", self.position().char + 1)
+    }
+}
+
+impl std::error::Error for UnpackError {}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use super::*;
+    use crate::{
+        impl_with_iterators::unpack_string_with_iterators,
+        impl_with_states::unpack_string_with_state,
+    };
+
+    /// Both engines must fail with the same error.
+    #[track_caller]
+    fn assert_render(input: &str, expected: &Expect) {
+        let error = unpack_string_with_iterators(input).expect_err("invalid input");
+        assert_eq!(
+            unpack_string_with_state(input).expect_err("invalid input"),
+            error
+        );
+
+        expected.assert_eq(&error.render(input));
+    }
+
+    #[test]
+    fn render() {
+        assert_render(
+            "4bc",
+            &expect![[r"
+                error: number doesn't repeat anything at character 1
+                4bc
+                ^
+            "]],
+        );
+        assert_render(
+            "ab2\\",
+            &expect![[r"
+                error: last escape character doesn't escape anything at character 4
+                ab2\
+                   ^
+            "]],
+        );
+        assert_render(
+            "日本3\\x",
+            &expect![[r#"
+                error: cannot escape 'x' at character 5
+                日本3\x
+                      ^
+            "#]],
+        );
+        assert_render(
+            "a99999999999999999999999",
+            &expect![[r"
+                error: repeat count is too large at character 21
+                a99999999999999999999999
+                                    ^
+            "]],
+        );
+        assert_render(
+            "\\\\99999999999999999999",
+            &expect![[r"
+                error: repeat count is too large at character 22
+                \\99999999999999999999
+                                     ^
+            "]],
+        );
+    }
+
+    #[test]
+    fn positions() {
+        let error = unpack_string_with_state("é\\é").expect_err("invalid input");
+        assert_eq!(
+            error,
+            UnpackError::InvalidEscape {
+                char: 'é',
+                position: Position { byte: 3, char: 2 },
+            }
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::iter::Peekable;
+
+use crate::{
+    error::{CharPositions, Position, UnpackError},
+    streaming::{parse_count, Limits},
+};
+
+type Result<T> = std::result::Result<T, UnpackError>;
+
+/// Maximum nesting of groups, the parser and the expansion are recursive.
+const MAX_DEPTH: usize = 128;
+
+/// A character or a group, repeated `count` times.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Item {
+    pub(crate) kind: ItemKind,
+    pub(crate) count: usize,
+    /// Position of the character, of its escape or of the opening parenthesis.
+    pub(crate) position: Position,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ItemKind {
+    Char(char),
+    Group(Vec<Item>),
+}
+
+/// Parses the extended grammar: the packed strings of the other engines, plus
+/// `\n`, `\t`, `\r` and `\u{1F600}` escapes and groups like `(ab)3`, which can be nested.
+///
+/// Parentheses are escaped as `\(` and `\)`.
+/// The limits are checked while parsing, so nothing is unpacked for an input that exceeds them;
+/// the output limit applies to every group, even one that is repeated zero times.
+struct Parser<'a> {
+    chars: Peekable<CharPositions<'a>>,
+    limits: Limits,
+}
+
+impl Parser<'_> {
+    /// Parses items up to the end of the group opened at `open`, or up to the end of the string,
+    /// returns them with the size of their unpacked string in bytes.
+    fn parse_items(&mut self, open: Option<Position>, depth: usize) -> Result<(Vec<Item>, usize)> {
+        let mut items = Vec::new();
+        let mut size = 0_usize;
+
+        loop {
+            let Some((position, char)) = self.chars.next() else {
+                return match open {
+                    Some(position) => Err(UnpackError::UnclosedGroup { position }),
+                    None => Ok((items, size)),
+                };
+            };
+
+            let (kind, item_size) = match char {
+                ')' if open.is_some() => return Ok((items, size)),
+                ')' => return Err(UnpackError::UnmatchedParen { position }),
+                '(' if depth == MAX_DEPTH => {
+                    return Err(UnpackError::NestingLimit {
+                        limit: MAX_DEPTH,
+                        position,
+                    })
+                }
+                '(' => {
+                    let (group, size) = self.parse_items(Some(position), depth + 1)?;
+                    (ItemKind::Group(group), size)
+                }
+                digit if digit.is_ascii_digit() => {
+                    return Err(UnpackError::LeadingDigit { position })
+                }
+                '\\' => {
+                    let char = self.parse_escape(position)?;
+                    (ItemKind::Char(char), char.len_utf8())
+                }
+                char => (ItemKind::Char(char), char.len_utf8()),
+            };
+
+            let count = parse_count(&mut self.chars, self.limits)?;
+            size = count
+                .checked_mul(item_size)
+                .and_then(|item_size| item_size.checked_add(size))
+                .filter(|&size| size <= self.limits.max_output)
+                .ok_or(UnpackError::OutputLimit {
+                    limit: self.limits.max_output,
+                    position,
+                })?;
+
+            items.push(Item {
+                kind,
+                count,
+                position,
+            });
+        }
+    }
+
+    /// Parses the character after the backslash at `backslash`.
+    fn parse_escape(&mut self, backslash: Position) -> Result<char> {
+        let Some((position, char)) = self.chars.next() else {
+            return Err(UnpackError::DanglingEscape {
+                position: backslash,
+            });
+        };
+
+        match char {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            'u' => self.parse_unicode(position),
+            char if char.is_ascii_digit() || matches!(char, '\\' | '(' | ')') => Ok(char),
+            char => Err(UnpackError::InvalidEscape { char, position }),
+        }
+    }
+
+    /// Parses `{XXXX}` after the `u` at `position`.
+    fn parse_unicode(&mut self, position: Position) -> Result<char> {
+        let error = UnpackError::InvalidUnicodeEscape { position };
+        self.chars
+            .next_if(|&(_, char)| char == '{')
+            .ok_or(error.clone())?;
+
+        let mut value = 0_u32;
+        let mut digits = 0;
+        while let Some((_, digit)) = self.chars.next_if(|(_, char)| char.is_ascii_hexdigit()) {
+            if digits == 6 {
+                return Err(error);
+            }
+            value = value * 16 + digit.to_digit(16).expect("hex digit");
+            digits += 1;
+        }
+
+        if digits == 0 {
+            return Err(error);
+        }
+        self.chars
+            .next_if(|&(_, char)| char == '}')
+            .ok_or(error.clone())?;
+
+        char::from_u32(value).ok_or(error)
+    }
+}
+
+/// Parses the string into items, checking the limits.
+pub(crate) fn parse(string: &str, limits: Limits) -> Result<(Vec<Item>, usize)> {
+    Parser {
+        chars: CharPositions::new(string).peekable(),
+        limits,
+    }
+    .parse_items(None, 0)
+}
+
+fn expand(items: &[Item], output: &mut String) {
+    for item in items {
+        for _ in 0..item.count {
+            match &item.kind {
+                ItemKind::Char(char) => output.push(*char),
+                ItemKind::Group(group) => expand(group, output),
+            }
+        }
+    }
+}
+
+/// Unpacks a string of the extended grammar, see [`Parser`].
+pub(crate) fn unpack_extended(string: &str, limits: Limits) -> Result<String> {
+    let (items, size) = parse(string, limits)?;
+    let mut output = String::with_capacity(size);
+    expand(&items, &mut output);
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::impl_with_states::unpack_string_with_state;
+
+    const UNLIMITED: Limits = Limits {
+        max_output: usize::MAX,
+        max_count: usize::MAX,
+    };
+
+    fn unpack(string: &str) -> Result<String> {
+        unpack_extended(string, Limits::default())
+    }
+
+    #[track_caller]
+    fn assert_render(input: &str, limits: Limits, expected: &Expect) {
+        let error = unpack_extended(input, limits).expect_err("invalid input");
+        expected.assert_eq(&error.render(input));
+    }
+
+    #[test]
+    fn unpack_groups() {
+        assert_eq!(unpack("(ab)3"), Ok("ababab".to_owned()));
+        assert_eq!(unpack("(a(bc)2)2"), Ok("abcbcabcbc".to_owned()));
+        assert_eq!(unpack("x(y)0z()5(()2)"), Ok("xz".to_owned()));
+        assert_eq!(unpack("(é2)2🦀"), Ok("éééé🦀".to_owned()));
+    }
+
+    #[test]
+    fn unpack_escapes() {
+        assert_eq!(unpack(r"a\n2\t\r"), Ok("a\n\n\t\r".to_owned()));
+        assert_eq!(unpack(r"\u{1F600}3\u{e9}"), Ok("😀😀😀é".to_owned()));
+        assert_eq!(unpack(r"\(\)2\\\4"), Ok(r"())\4".to_owned()));
+    }
+
+    #[test]
+    fn errors() {
+        let limits = Limits::default();
+        assert_render(
+            "a(b(c)2",
+            limits,
+            &expect![[r"
+                error: group is never closed at character 2
+                a(b(c)2
+                 ^
+            "]],
+        );
+        assert_render(
+            "(a)2b)",
+            limits,
+            &expect![[r"
+                error: unmatched closing parenthesis at character 6
+                (a)2b)
+                     ^
+            "]],
+        );
+        assert_render(
+            "a(3b)",
+            limits,
+            &expect![[r"
+                error: number doesn't repeat anything at character 3
+                a(3b)
+                  ^
+            "]],
+        );
+        assert_render(
+            r"ab\u{110000}",
+            limits,
+            &expect![[r"
+                error: invalid unicode escape, expected \u{XXXX} at character 4
+                ab\u{110000}
+                   ^
+            "]],
+        );
+        for input in [r"\u1234", r"\u{}", r"\u{1F600", r"\u{1234567}"] {
+            let error = unpack(input).expect_err("invalid escape");
+            assert!(matches!(error, UnpackError::InvalidUnicodeEscape { .. }));
+        }
+        assert_render(
+            "((ab)1000(cd)2)1000",
+            Limits {
+                max_output: 1_000_000,
+                max_count: 1000,
+            },
+            &expect![[r"
+                error: unpacked string is larger than 1000000 bytes at character 1
+                ((ab)1000(cd)2)1000
+                ^
+            "]],
+        );
+
+        let nested = format!("{}a{}", "(".repeat(200), ")".repeat(200));
+        let error = unpack(&nested).expect_err("too deep");
+        expect!["groups are nested deeper than 128 at character 129"].assert_eq(&error.to_string());
+    }
+
+    proptest! {
+        /// Without parentheses and the new escapes the grammar is the same as the one of the other engines.
+        #[test]
+        fn matches_engines(input in r"[0-9]?(([a-c\\]|\\[0-9\\])[0-9]{0,4}){0,8}") {
+            prop_assert_eq!(unpack_extended(&input, UNLIMITED), unpack_string_with_state(&input));
+        }
+    }
+}
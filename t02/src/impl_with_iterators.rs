@@ -1,12 +1,17 @@
-use anyhow::{anyhow, Result};
 use itertools::{repeat_n, Itertools};
 
+use crate::error::{CharPositions, Position, UnpackError};
+
+type Chars<'a> = std::iter::Peekable<CharPositions<'a>>;
+
+type Result<T> = std::result::Result<T, UnpackError>;
+
 pub(crate) fn unpack_string_with_iterators(string: &str) -> Result<String> {
-    let unpacked_iterators = string.chars().peekable().batching(|iter| {
+    let unpacked_iterators = CharPositions::new(string).peekable().batching(|iter| {
         //        ****
         // peek: ^
         // iter: ^
-        let char = iter.next()?;
+        let (position, char) = iter.next()?;
 
         //        ****
         // peek:  ^
@@ -15,13 +20,11 @@ pub(crate) fn unpack_string_with_iterators(string: &str) -> Result<String> {
             //        N***...
             // peek:  ^
             // iter:  ^
-            digit if digit.is_ascii_digit() => {
-                Some(Err(anyhow!("found number that doesn't repeating anything")))
-            }
+            digit if digit.is_ascii_digit() => Some(Err(UnpackError::LeadingDigit { position })),
             //        \***...
             // peek:  ^
             // iter:  ^
-            '\\' => match_escape(iter),
+            '\\' => match_escape(iter, position),
             //        C**
             // peek:  ^
             // iter:  ^
@@ -32,14 +35,11 @@ pub(crate) fn unpack_string_with_iterators(string: &str) -> Result<String> {
     unpacked_iterators.process_results(|iter| iter.flatten().collect())
 }
 
-fn match_letter(
-    iter: &mut std::iter::Peekable<std::str::Chars<'_>>,
-    letter: char,
-) -> Option<Result<itertools::RepeatN<char>>> {
+fn match_letter(iter: &mut Chars<'_>, letter: char) -> Option<Result<itertools::RepeatN<char>>> {
     //        C**...
     // peek:   ^
     // iter:  ^
-    let Some(peeked_char) = iter.peek() else {
+    let Some((_, peeked_char)) = iter.peek() else {
         //        C
         // peek:   ^
         // iter:  ^
@@ -62,10 +62,10 @@ fn match_letter(
 }
 
 fn parse_maybe_number(
-    iter: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    iter: &mut Chars<'_>,
     repeating_char: char,
 ) -> Option<Result<itertools::RepeatN<char>>> {
-    let Some(char) = iter.peek() else {
+    let Some((_, char)) = iter.peek() else {
         return Some(Ok(repeat_n(repeating_char, 1)));
     };
 
@@ -73,17 +73,20 @@ fn parse_maybe_number(
         return Some(Ok(repeat_n(repeating_char, 1)));
     }
 
-    let mut number = 0;
+    let mut number: usize = 0;
     loop {
-        let digit = iter
-            .next()
-            .expect("expected char after peek")
-            .to_digit(10)
-            .expect("char is digit after check");
+        let (position, digit) = iter.next().expect("expected char after peek");
+        let digit = digit.to_digit(10).expect("char is digit after check");
         let digit = usize::try_from(digit).expect("this is digit");
-        number = number * 10 + digit;
+        let Some(next_number) = number
+            .checked_mul(10)
+            .and_then(|number| number.checked_add(digit))
+        else {
+            return Some(Err(UnpackError::CountOverflow { position }));
+        };
+        number = next_number;
 
-        let Some(char) = iter.peek() else {
+        let Some((_, char)) = iter.peek() else {
             return Some(Ok(repeat_n(repeating_char, number)));
         };
 
@@ -94,15 +97,16 @@ fn parse_maybe_number(
 }
 
 fn match_escape(
-    iter: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    iter: &mut Chars<'_>,
+    escape_position: Position,
 ) -> Option<Result<itertools::RepeatN<char>>> {
-    let Some(peeked_char) = iter.peek() else {
+    let Some(&(position, peeked_char)) = iter.peek() else {
         //        \
         // peek:   ^
         // iter:  ^
-        return Some(Err(anyhow!(
-            "last escape character doesn't escape anything"
-        )));
+        return Some(Err(UnpackError::DanglingEscape {
+            position: escape_position,
+        }));
     };
 
     match peeked_char {
@@ -110,14 +114,14 @@ fn match_escape(
         // peek:   ^
         // iter:  ^
         digit_as_char if digit_as_char.is_ascii_digit() => {
-            let digit_as_char = iter.next().expect("expected char after peek");
+            let (_, digit_as_char) = iter.next().expect("expected char after peek");
             parse_maybe_number(iter, digit_as_char)
         }
         //        \\**...
         // peek:   ^
         // iter:  ^
         '\\' => {
-            let Some('\\') = iter.next() else {
+            let Some((_, '\\')) = iter.next() else {
                 panic!("expected '\\' after peek");
             };
             //        \\**...
@@ -128,7 +132,7 @@ fn match_escape(
         //        \C**...
         // peek:   ^
         // iter:  ^
-        char => Some(Err(anyhow!("cannot escape '{char}'"))),
+        char => Some(Err(UnpackError::InvalidEscape { char, position })),
     }
 }
 
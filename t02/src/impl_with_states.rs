@@ -1,6 +1,7 @@
-use anyhow::{anyhow, Result};
 use itertools::repeat_n;
 
+use crate::error::{CharPositions, Position, UnpackError};
+
 #[derive(Debug)]
 enum State {
     Start,
@@ -16,7 +17,8 @@ enum CharacterRepeat {
 
 #[derive(Debug)]
 enum EscapeEntry {
-    First,
+    /// Position of the backslash, to report it if nothing is escaped.
+    First(Position),
     Second,
     Repeat(usize),
 }
@@ -43,27 +45,36 @@ fn determine_char_kind(char: char) -> CharKind {
     }
 }
 
-pub(crate) fn unpack_string_with_state(string: &str) -> Result<String> {
+pub(crate) fn unpack_string_with_state(string: &str) -> Result<String, UnpackError> {
     let mut result = Vec::new();
     let mut state = State::Start;
 
-    for char in string.chars() {
+    for (position, char) in CharPositions::new(string) {
         let char_kind = determine_char_kind(char);
+        let add_digit = |repeat: usize, digit: usize| {
+            repeat
+                .checked_mul(10)
+                .and_then(|repeat| repeat.checked_add(digit))
+                .ok_or(UnpackError::CountOverflow { position })
+        };
+
         match (state, char_kind) {
             (State::Start, CharKind::Digit(_)) => {
-                return Err(anyhow!("found digit that doesn't repeating anything"))
+                return Err(UnpackError::LeadingDigit { position })
             }
             (State::Start, CharKind::Character(character)) => {
                 state = State::Character(character, CharacterRepeat::One);
             }
-            (State::Start, CharKind::Escape) => state = State::Escape(EscapeEntry::First),
+            (State::Start, CharKind::Escape) => state = State::Escape(EscapeEntry::First(position)),
             (State::Character(character, repeat), CharKind::Digit(number)) => match repeat {
                 CharacterRepeat::One => {
                     state = State::Character(character, CharacterRepeat::Many(number));
                 }
                 CharacterRepeat::Many(repeat) => {
-                    state =
-                        State::Character(character, CharacterRepeat::Many(repeat * 10 + number));
+                    state = State::Character(
+                        character,
+                        CharacterRepeat::Many(add_digit(repeat, number)?),
+                    );
                 }
             },
             (State::Character(character, repeat), CharKind::Character(next_character)) => {
@@ -72,10 +83,10 @@ pub(crate) fn unpack_string_with_state(string: &str) -> Result<String> {
             }
             (State::Character(character, repeat), CharKind::Escape) => {
                 push_repeating_character(repeat, &mut result, character);
-                state = State::Escape(EscapeEntry::First);
+                state = State::Escape(EscapeEntry::First(position));
             }
             (State::Escape(entry), CharKind::Digit(digit)) => match entry {
-                EscapeEntry::First => {
+                EscapeEntry::First(_) => {
                     state = State::Character(
                         char::from_digit(u32::try_from(digit).expect("digit"), 10).expect("digit"),
                         CharacterRepeat::One,
@@ -83,12 +94,14 @@ pub(crate) fn unpack_string_with_state(string: &str) -> Result<String> {
                 }
                 EscapeEntry::Second => state = State::Escape(EscapeEntry::Repeat(digit)),
                 EscapeEntry::Repeat(repeat) => {
-                    state = State::Escape(EscapeEntry::Repeat(repeat * 10 + digit));
+                    state = State::Escape(EscapeEntry::Repeat(add_digit(repeat, digit)?));
                 }
             },
             (State::Escape(entry), CharKind::Character(character)) => {
                 match entry {
-                    EscapeEntry::First => return Err(anyhow!("cannot escape '{char}'")),
+                    EscapeEntry::First(_) => {
+                        return Err(UnpackError::InvalidEscape { char, position })
+                    }
                     EscapeEntry::Second => {
                         result.push(repeat_n('\\', 1));
                     }
@@ -99,14 +112,14 @@ pub(crate) fn unpack_string_with_state(string: &str) -> Result<String> {
                 state = State::Character(character, CharacterRepeat::One);
             }
             (State::Escape(entry), CharKind::Escape) => match entry {
-                EscapeEntry::First => state = State::Escape(EscapeEntry::Second),
+                EscapeEntry::First(_) => state = State::Escape(EscapeEntry::Second),
                 EscapeEntry::Second => {
                     result.push(repeat_n('\\', 1));
-                    state = State::Escape(EscapeEntry::First);
+                    state = State::Escape(EscapeEntry::First(position));
                 }
                 EscapeEntry::Repeat(repeat) => {
                     result.push(repeat_n('\\', repeat));
-                    state = State::Escape(EscapeEntry::First);
+                    state = State::Escape(EscapeEntry::First(position));
                 }
             },
         }
@@ -119,9 +132,7 @@ pub(crate) fn unpack_string_with_state(string: &str) -> Result<String> {
             CharacterRepeat::Many(repeat) => result.push(repeat_n(character, repeat)),
         },
         State::Escape(escape_entry) => match escape_entry {
-            EscapeEntry::First => {
-                return Err(anyhow!("last escape character doesn't escape anything"))
-            }
+            EscapeEntry::First(position) => return Err(UnpackError::DanglingEscape { position }),
             EscapeEntry::Second => result.push(repeat_n('\\', 1)),
             EscapeEntry::Repeat(repeat) => result.push(repeat_n('\\', repeat)),
         },
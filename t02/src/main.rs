@@ -1,13 +1,411 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufWriter, Read, Write},
+    process::ExitCode,
+};
+
+use anyhow::Result;
+use binary::RatioReport;
+use camino::Utf8PathBuf;
+use clap::Parser;
+use error::UnpackError;
+use streaming::Limits;
+
+mod binary;
+#[cfg(test)]
+mod differential;
+mod error;
+mod grammar;
 mod impl_with_iterators;
 mod impl_with_states;
+mod pack;
+mod streaming;
+
+/// Unpacks strings like `a4bc2d5e` into `aaaabccddddde`, or packs them back.
+///
+/// Every string is processed separately and printed on its own line.
+/// Exit status is 0 if all strings are valid, 1 if some strings can't be unpacked,
+/// and 2 if an input can't be read.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Strings to process. With no strings and no files, read lines from standard input.
+    strings: Vec<String>,
+
+    /// Read strings line by line from the file, `-` for standard input. Can be repeated.
+    #[arg(short, long = "file", value_name = "FILE")]
+    files: Vec<Utf8PathBuf>,
+
+    /// Pack strings instead of unpacking them.
+    #[arg(short, long, conflicts_with = "check")]
+    pack: bool,
+
+    /// Only check that strings can be unpacked, print nothing but errors.
+    #[arg(short, long)]
+    check: bool,
+
+    /// Report the sizes of the inputs in the binary and in the textual run-length encodings,
+    /// files are read whole as binary data.
+    #[arg(long, conflicts_with_all = ["pack", "check"])]
+    ratio: bool,
+
+    /// Encode or decode the inputs with the binary or the textual run-length encoding of bytes,
+    /// files are streamed to standard output.
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with_all = ["pack", "check", "ratio"])]
+    binary: Option<BinaryMode>,
+
+    /// Implementation that unpacks strings.
+    #[arg(long, value_enum, default_value_t)]
+    engine: Engine,
+
+    /// Maximum size of an unpacked string in bytes, for the streaming and extended engines.
+    #[arg(long, default_value_t = Limits::default().max_output)]
+    max_output: usize,
+
+    /// Maximum repeat count of a character or a group, for the streaming and extended engines.
+    #[arg(long, default_value_t = Limits::default().max_count)]
+    max_count: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Engine {
+    /// Iterator adapters, the output is collected in memory without limits.
+    Iterators,
+    /// A state machine, the output is collected in memory without limits.
+    State,
+    /// Unpacks runs one by one, the output size and repeat counts are limited.
+    #[default]
+    Streaming,
+    /// The extended grammar with `\n`, `\t`, `\u{1F600}` escapes and nested groups like `(ab)3`,
+    /// with the same limits as the streaming engine.
+    Extended,
+}
+
+/// Run-length encodings of binary data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum BinaryMode {
+    /// Encode into the compact binary format with varint counts and literal runs.
+    Encode,
+    /// Decode the compact binary format, the output size is limited.
+    Decode,
+    /// Encode into the textual form of the extended grammar.
+    EncodeText,
+    /// Decode the textual form, every unpacked character must be a byte.
+    DecodeText,
+}
+
+impl Args {
+    /// Files to read, standard input if there are neither strings nor files.
+    fn files(&self) -> Vec<Utf8PathBuf> {
+        if self.strings.is_empty() && self.files.is_empty() {
+            vec![Utf8PathBuf::from("-")]
+        } else {
+            self.files.clone()
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            max_output: self.max_output,
+            max_count: self.max_count,
+        }
+    }
+
+    /// Unpacks or packs a single string into the output,
+    /// the output is not changed if the string is invalid.
+    fn process(&self, string: &str, output: &mut impl Write) -> Result<()> {
+        if self.pack {
+            writeln!(output, "{}", pack::pack_string(string))?;
+            return Ok(());
+        }
+
+        let unpacked = match self.engine {
+            Engine::Iterators => impl_with_iterators::unpack_string_with_iterators(string)?,
+            Engine::State => impl_with_states::unpack_string_with_state(string)?,
+            Engine::Streaming if self.check => {
+                streaming::unpack_to_writer(string, self.limits(), io::sink())?;
+                return Ok(());
+            }
+            Engine::Streaming => {
+                streaming::unpack_chars(string, self.limits()).collect::<Result<String, _>>()?
+            }
+            Engine::Extended => grammar::unpack_extended(string, self.limits())?,
+        };
+
+        if !self.check {
+            writeln!(output, "{unpacked}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Exit status of the whole run, the worst status among the strings wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Valid = 0,
+    Invalid = 1,
+    Unreadable = 2,
+}
+
+/// Processes a string and reports it to standard error if it's invalid.
+fn process_string(
+    args: &Args,
+    location: &str,
+    string: &str,
+    output: &mut impl Write,
+) -> Result<Status> {
+    match args.process(string, output) {
+        Ok(()) => Ok(Status::Valid),
+        Err(error) => match error.downcast::<UnpackError>() {
+            Ok(error) => {
+                eprint!("t02: {location}: {}", error.render(string));
+                Ok(Status::Invalid)
+            }
+            Err(error) => Err(error),
+        },
+    }
+}
+
+/// Processes a string or a file as binary data, all of it is read unless it's streamed.
+fn process_binary(
+    args: &Args,
+    name: &str,
+    mut reader: impl Read,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let mut content = Vec::new();
+
+    match args.binary {
+        Some(BinaryMode::Encode) => {
+            binary::encode_to_writer(reader, output)?;
+        }
+        Some(BinaryMode::Decode) => {
+            binary::decode_to_writer(reader, args.limits(), output)?;
+        }
+        Some(BinaryMode::EncodeText) => {
+            reader.read_to_end(&mut content)?;
+            writeln!(output, "{}", binary::encode_text(&content))?;
+        }
+        Some(BinaryMode::DecodeText) => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            output.write_all(&binary::decode_text(text, args.limits())?)?;
+        }
+        None => {
+            reader.read_to_end(&mut content)?;
+            writeln!(output, "{name}: {}", RatioReport::new(&content))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Processes the strings and the files as binary data, for `--ratio` and `--binary`.
+fn run_binary(args: &Args, output: &mut impl Write) -> Result<Status> {
+    let mut status = Status::Valid;
+
+    let strings = args.strings.iter().enumerate().map(|(index, string)| {
+        let reader: io::Result<Box<dyn Read>> = Ok(Box::new(string.as_bytes()));
+        (format!("argument {}", index + 1), reader)
+    });
+    let files = args.files().into_iter().map(|path| {
+        let reader: io::Result<Box<dyn Read>> = if path == "-" {
+            Ok(Box::new(io::stdin().lock()))
+        } else {
+            File::open(&path).map(|file| Box::new(io::BufReader::new(file)) as _)
+        };
+        let name = match path.as_str() {
+            "-" => "standard input".to_owned(),
+            path => path.to_owned(),
+        };
+        (name, reader)
+    });
+
+    for (name, reader) in strings.chain(files) {
+        let Err(error) = reader.and_then(|reader| process_binary(args, &name, reader, output))
+        else {
+            continue;
+        };
+
+        eprintln!("t02: {name}: {error}");
+        status = status.max(match error.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Status::Invalid,
+            _ => Status::Unreadable,
+        });
+    }
+
+    output.flush()?;
+
+    Ok(status)
+}
+
+fn run(args: &Args, output: &mut impl Write) -> Result<Status> {
+    if args.ratio || args.binary.is_some() {
+        return run_binary(args, output);
+    }
+
+    let mut status = Status::Valid;
+
+    for (index, string) in args.strings.iter().enumerate() {
+        let location = format!("argument {}", index + 1);
+        status = status.max(process_string(args, &location, string, output)?);
+    }
+
+    for path in args.files() {
+        let (name, reader): (_, Box<dyn BufRead>) = if path == "-" {
+            ("standard input".to_owned(), Box::new(io::stdin().lock()))
+        } else {
+            match File::open(&path) {
+                Ok(file) => (path.to_string(), Box::new(io::BufReader::new(file))),
+                Err(error) => {
+                    eprintln!("t02: {path}: {error}");
+                    status = Status::Unreadable;
+                    continue;
+                }
+            }
+        };
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => {
+                    eprintln!("t02: {name}:{}: {error}", index + 1);
+                    status = Status::Unreadable;
+                    break;
+                }
+            };
+
+            let location = format!("{name}:{}", index + 1);
+            status = status.max(process_string(args, &location, &line, output)?);
+        }
+    }
+
+    output.flush()?;
+
+    Ok(status)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let mut output = BufWriter::new(io::stdout().lock());
+
+    match run(&args, &mut output) {
+        Ok(status) => ExitCode::from(status as u8),
+        Err(error) => {
+            eprintln!("t02: {error:#}");
+            ExitCode::from(Status::Unreadable as u8)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+
+    use super::*;
+
+    #[track_caller]
+    fn assert_process(args: &[&str], string: &str, expected: &Expect) {
+        let args = Args::parse_from(std::iter::once("t02").chain(args.iter().copied()));
+        let mut output = Vec::new();
+
+        let actual = match args.process(string, &mut output) {
+            Ok(()) => String::from_utf8(output).expect("output is UTF-8"),
+            Err(error) => format!("{error:#}"),
+        };
+        expected.assert_eq(&actual);
+    }
+
+    #[test]
+    fn args() {
+        let args = Args::parse_from(["t02", "a4", "-f", "input.txt", "--engine", "state"]);
+        expect![[r#"Args { strings: ["a4"], files: ["input.txt"], pack: false, check: false, ratio: false, binary: None, engine: State, max_output: 67108864, max_count: 1000000 }"#]]
+            .assert_eq(&format!("{args:?}"));
+
+        assert!(Args::try_parse_from(["t02", "--pack", "--check"]).is_err());
+        assert!(Args::try_parse_from(["t02", "--ratio", "--pack"]).is_err());
+    }
+
+    #[test]
+    fn process() {
+        for engine in ["iterators", "state", "streaming", "extended"] {
+            assert_process(
+                &["--engine", engine],
+                "a4bc2d5e",
+                &expect![[r#"
+                aaaabccddddde
+            "#]],
+            );
+            assert_process(&["--engine", engine, "--check"], "a4bc2d5e", &expect![""]);
+            assert_process(
+                &["--engine", engine, "--check"],
+                "a\\b",
+                &expect!["cannot escape 'b' at character 3"],
+            );
+        }
 
-fn main() {
-    let string =
-        impl_with_iterators::unpack_string_with_iterators("a4bc2d5e").expect("smoke testing");
+        assert_process(
+            &["--pack"],
+            "aaaabccddddde",
+            &expect![[r#"
+            a4bc2d5e
+        "#]],
+        );
+        assert_process(
+            &["--engine", "extended"],
+            "(ab)2\\u{e9}",
+            &expect![[r#"
+                ababé
+            "#]],
+        );
+        assert_process(
+            &["--max-count", "3"],
+            "a4",
+            &expect!["repeat count is larger than 3 at character 2"],
+        );
+    }
 
-    println!("{string}");
+    #[track_caller]
+    fn assert_binary(args: &[&str], input: &[u8], expected: &Expect) {
+        let args = Args::parse_from(std::iter::once("t02").chain(args.iter().copied()));
+        let mut output = Vec::new();
 
-    let string = impl_with_states::unpack_string_with_state("a4bc2d5e").expect("smoke testing");
+        let actual = match process_binary(&args, "input", input, &mut output) {
+            Ok(()) => output.escape_ascii().to_string(),
+            Err(error) => error.to_string(),
+        };
+        expected.assert_eq(&actual);
+    }
 
-    println!("{string}");
+    #[test]
+    fn binary() {
+        assert_binary(
+            &["--binary", "encode-text"],
+            b"aaab\x00\x00",
+            &expect![[r#"a3b\\u{0}2\n"#]],
+        );
+        assert_binary(
+            &["--binary", "decode-text"],
+            b"a3(bc)2\n",
+            &expect!["aaabcbc"],
+        );
+        assert_binary(
+            &["--binary", "decode-text"],
+            b"a\\x",
+            &expect!["cannot escape 'x' at character 3"],
+        );
+        assert_binary(&["--binary", "encode"], b"xxxxxx", &expect![[r#"\x07x"#]]);
+        assert_binary(&["--binary", "decode"], b"\x05x", &expect!["xxxxx"]);
+        assert_binary(
+            &["--binary", "decode", "--max-output", "3"],
+            b"\x05x",
+            &expect!["decoded data is larger than 3 bytes"],
+        );
+        assert_binary(
+            &["--ratio"],
+            b"abc",
+            &expect![[r#"input: 3 bytes, binary 4 bytes (133.3%), text 3 bytes (100.0%)\n"#]],
+        );
+    }
 }
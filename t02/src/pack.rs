@@ -0,0 +1,79 @@
+use itertools::Itertools;
+
+/// Packs runs of equal characters into a character followed by the run length,
+/// the inverse of unpacking: `aaaabccddddde` is packed into `a4bc2d5e`.
+///
+/// Digits and backslashes are escaped with a backslash, so the result unpacks
+/// to exactly the original string.
+pub(crate) fn pack_string(string: &str) -> String {
+    let mut packed = String::new();
+
+    for (char, run) in &string.chars().chunk_by(|&char| char) {
+        if char.is_ascii_digit() || char == '\\' {
+            packed.push('\\');
+        }
+        packed.push(char);
+
+        match run.count() {
+            1 => {}
+            count => packed.push_str(&count.to_string()),
+        }
+    }
+
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{
+        impl_with_iterators::unpack_string_with_iterators,
+        impl_with_states::unpack_string_with_state,
+    };
+
+    #[track_caller]
+    fn assert_pack(actual: &str, expected: &Expect) {
+        expected.assert_eq(&pack_string(actual));
+    }
+
+    #[test]
+    fn pack() {
+        assert_pack("", &expect![""]);
+        assert_pack("aaaabccddddde", &expect!["a4bc2d5e"]);
+        assert_pack("abcd", &expect!["abcd"]);
+        assert_pack("aaaaaaaaaaaa", &expect!["a12"]);
+        assert_pack("🦀🦀🦀日本", &expect!["🦀3日本"]);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_pack("qwe45", &expect![[r"qwe\4\5"]]);
+        assert_pack("qwe44444", &expect![[r"qwe\45"]]);
+        assert_pack(r"qwe\\\\\", &expect![[r"qwe\\5"]]);
+        assert_pack(r"a\1", &expect![[r"a\\\1"]]);
+    }
+
+    /// Strings with long runs of characters, including digits and backslashes.
+    fn runs() -> impl Strategy<Value = String> {
+        let char = prop_oneof![any::<char>(), Just('\\'), Just('1'), Just('0'), Just('a')];
+
+        prop::collection::vec((char, 1..30_usize), 0..10).prop_map(|runs| {
+            runs.into_iter()
+                .map(|(char, count)| char.to_string().repeat(count))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(string in prop_oneof![any::<String>(), runs()]) {
+            let packed = pack_string(&string);
+
+            prop_assert_eq!(&unpack_string_with_iterators(&packed).expect("packed is valid"), &string);
+            prop_assert_eq!(&unpack_string_with_state(&packed).expect("packed is valid"), &string);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{io::Write, iter::Peekable};
+
+use anyhow::Result;
+use itertools::repeat_n;
+
+use crate::error::{CharPositions, Position, UnpackError};
+
+/// Limits that keep unpacking of untrusted input bounded, like `a99999999999`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Limits {
+    /// Maximum size of the unpacked string in bytes.
+    pub(crate) max_output: usize,
+    /// Maximum repeat count of a single character.
+    pub(crate) max_count: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_output: 64 * 1024 * 1024,
+            max_count: 1_000_000,
+        }
+    }
+}
+
+/// A character repeated `count` times.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Run {
+    pub(crate) char: char,
+    pub(crate) count: usize,
+}
+
+/// Parses the packed string into runs one by one, checking the limits before anything is unpacked.
+#[derive(Clone, Debug)]
+pub(crate) struct Runs<'a> {
+    chars: Peekable<CharPositions<'a>>,
+    limits: Limits,
+    /// Size of the runs parsed so far in bytes.
+    output: usize,
+    failed: bool,
+}
+
+impl<'a> Runs<'a> {
+    pub(crate) fn new(string: &'a str, limits: Limits) -> Self {
+        Self {
+            chars: CharPositions::new(string).peekable(),
+            limits,
+            output: 0,
+            failed: false,
+        }
+    }
+
+    fn parse_run(&mut self) -> Option<Result<Run, UnpackError>> {
+        let (position, char) = self.chars.next()?;
+
+        let char = match char {
+            digit if digit.is_ascii_digit() => {
+                return Some(Err(UnpackError::LeadingDigit { position }))
+            }
+            '\\' => match self.chars.next() {
+                Some((_, char)) if char.is_ascii_digit() || char == '\\' => char,
+                Some((position, char)) => {
+                    return Some(Err(UnpackError::InvalidEscape { char, position }))
+                }
+                None => return Some(Err(UnpackError::DanglingEscape { position })),
+            },
+            char => char,
+        };
+
+        let count = match parse_count(&mut self.chars, self.limits) {
+            Ok(count) => count,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let size = count
+            .checked_mul(char.len_utf8())
+            .and_then(|size| size.checked_add(self.output))
+            .filter(|&size| size <= self.limits.max_output);
+        let Some(size) = size else {
+            return Some(Err(UnpackError::OutputLimit {
+                limit: self.limits.max_output,
+                position,
+            }));
+        };
+        self.output = size;
+
+        Some(Ok(Run { char, count }))
+    }
+}
+
+/// Parses the digits after a character, a missing count is one.
+pub(crate) fn parse_count(
+    chars: &mut Peekable<CharPositions<'_>>,
+    limits: Limits,
+) -> Result<usize, UnpackError> {
+    let mut count: Option<(Position, usize)> = None;
+
+    while let Some(&(position, digit)) = chars.peek() {
+        let Some(digit) = digit.to_digit(10) else {
+            break;
+        };
+        chars.next();
+
+        let (start, number) = count.unwrap_or((position, 0));
+        let number = number
+            .checked_mul(10)
+            .and_then(|number| number.checked_add(usize::try_from(digit).expect("digit")))
+            .ok_or(UnpackError::CountOverflow { position })?;
+        if number > limits.max_count {
+            return Err(UnpackError::CountLimit {
+                limit: limits.max_count,
+                position: start,
+            });
+        }
+        count = Some((start, number));
+    }
+
+    Ok(count.map_or(1, |(_, count)| count))
+}
+
+impl Iterator for Runs<'_> {
+    type Item = Result<Run, UnpackError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let run = self.parse_run();
+        self.failed = matches!(run, Some(Err(_)));
+
+        run
+    }
+}
+
+/// Unpacks the string lazily, character by character.
+///
+/// The iterator yields an error and stops at the first invalid run,
+/// the characters before it are yielded.
+pub(crate) fn unpack_chars(
+    string: &str,
+    limits: Limits,
+) -> impl Iterator<Item = Result<char, UnpackError>> + '_ {
+    Runs::new(string, limits).flat_map(|run| match run {
+        Ok(Run { char, count }) => repeat_n(Ok(char), count),
+        Err(error) => repeat_n(Err(error), 1),
+    })
+}
+
+/// Size of the chunks the output is written in.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Unpacks the string into the writer without collecting the output in memory,
+/// returns the number of written bytes.
+///
+/// An [`UnpackError`] is returned after the output before the invalid run is written.
+pub(crate) fn unpack_to_writer(
+    string: &str,
+    limits: Limits,
+    mut writer: impl Write,
+) -> Result<usize> {
+    let mut written = 0;
+    let mut chunk = String::with_capacity(CHUNK_SIZE);
+
+    for char in unpack_chars(string, limits) {
+        let char = match char {
+            Ok(char) => char,
+            Err(error) => {
+                writer.write_all(chunk.as_bytes())?;
+                return Err(error.into());
+            }
+        };
+        chunk.push(char);
+
+        if chunk.len() >= CHUNK_SIZE - char.len_utf8() {
+            writer.write_all(chunk.as_bytes())?;
+            written += chunk.len();
+            chunk.clear();
+        }
+    }
+
+    writer.write_all(chunk.as_bytes())?;
+    written += chunk.len();
+    writer.flush()?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{
+        impl_with_iterators::unpack_string_with_iterators,
+        impl_with_states::unpack_string_with_state,
+    };
+
+    const UNLIMITED: Limits = Limits {
+        max_output: usize::MAX,
+        max_count: usize::MAX,
+    };
+
+    fn unpack(string: &str, limits: Limits) -> Result<String, UnpackError> {
+        unpack_chars(string, limits).collect()
+    }
+
+    #[track_caller]
+    fn assert_limited(input: &str, limits: Limits, expected: &Expect) {
+        let error = unpack(input, limits).expect_err("limit is exceeded");
+        expected.assert_eq(&error.render(input));
+    }
+
+    #[test]
+    fn unpack_streaming() {
+        let limits = Limits::default();
+        assert_eq!(unpack("a4bc2d5e", limits), Ok("aaaabccddddde".to_owned()));
+        assert_eq!(unpack("qwe\\45\\\\2", limits), Ok(r"qwe44444\\".to_owned()));
+        assert_eq!(unpack("a0b", limits), Ok("b".to_owned()));
+
+        let mut output = Vec::new();
+        let written = unpack_to_writer("🦀3a20000", limits, &mut output).expect("valid input");
+        assert_eq!(written, 12 + 20000);
+        assert_eq!(output, ["🦀🦀🦀", &"a".repeat(20000)].concat().as_bytes());
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            max_output: 10,
+            max_count: 5,
+        };
+        assert_eq!(unpack("a5b5", limits), Ok("aaaaabbbbb".to_owned()));
+
+        assert_limited(
+            "a99999999999999",
+            limits,
+            &expect![[r"
+                error: repeat count is larger than 5 at character 2
+                a99999999999999
+                 ^
+            "]],
+        );
+        assert_limited(
+            "a5b2🦀",
+            limits,
+            &expect![[r"
+                error: unpacked string is larger than 10 bytes at character 5
+                a5b2🦀
+                    ^
+            "]],
+        );
+
+        let error = unpack("a99999999999999999999", UNLIMITED).expect_err("overflow");
+        assert!(matches!(error, UnpackError::CountOverflow { .. }));
+        // the size is checked before anything is unpacked
+        let error = unpack("a99999999999", Limits::default()).expect_err("too large");
+        assert!(matches!(error, UnpackError::CountLimit { .. }));
+    }
+
+    #[test]
+    fn writer_stops_at_error() {
+        let mut output = Vec::new();
+        let error =
+            unpack_to_writer("ab3\\x", Limits::default(), &mut output).expect_err("invalid escape");
+
+        assert_eq!(output, b"abbb");
+        expect!["cannot escape 'x' at character 5"].assert_eq(&error.to_string());
+    }
+
+    proptest! {
+        /// Counts are at most 4 digits, so the engines without limits don't allocate too much.
+        #[test]
+        fn matches_engines(input in r"[0-9]?(([a-c\\]|\\[0-9\\])[0-9]{0,4}){0,8}") {
+            let expected = unpack_string_with_state(&input);
+            prop_assert_eq!(&unpack_string_with_iterators(&input), &expected);
+            prop_assert_eq!(unpack(&input, UNLIMITED), expected);
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Split, Write},
+};
+
+use anyhow::Result;
+use tempfile::{NamedTempFile, TempPath};
+
+use crate::sort::{self, Sort};
+
+/// Memory taken by a line besides its bytes.
+const LINE_OVERHEAD: usize = std::mem::size_of::<String>();
+
+/// Most runs merged at once, like GNU sort. More runs are merged in several passes,
+/// so only this many temporary files are open at a time.
+const MERGE_FAN_IN: usize = 16;
+
+/// A sorted run, spilled to a temporary file or given as a sorted input, read line by line.
+struct Run<'input> {
+    lines: Split<Box<dyn BufRead + 'input>>,
+}
+
+impl<'input> Run<'input> {
+    fn new(input: Box<dyn BufRead + 'input>) -> Self {
+        Self {
+            lines: input.split(b'\n'),
+        }
+    }
+
+    fn open(path: &TempPath) -> Result<Self> {
+        Ok(Self::new(Box::new(BufReader::new(File::open(path)?))))
+    }
+
+    /// Reads the next line, checking that its keys can be compared.
+    fn next_line(&mut self, sort: &Sort) -> Result<Option<String>> {
+        let Some(line) = self.lines.next() else {
+            return Ok(None);
+        };
+        let line = String::from_utf8(line?)?;
+        sort.prepare_comparable_line(&line)?;
+
+        Ok(Some(line))
+    }
+}
+
+/// The smallest line of a run not merged yet, ties go to the earlier run.
+struct Head<'sort> {
+    sort: &'sort Sort,
+    line: String,
+    run: usize,
+}
+
+impl Ord for Head<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort
+            .compare_lines(&self.line, &other.line)
+            .expect("lines are checked when read")
+            .then(self.run.cmp(&other.run))
+    }
+}
+
+impl PartialOrd for Head<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Head<'_> {}
+
+/// Writes lines to a temporary file, one per line, and closes it until it's merged.
+fn write_run(lines: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<TempPath> {
+    let mut writer = BufWriter::new(NamedTempFile::new()?);
+    lines(&mut writer)?;
+
+    Ok(writer.into_inner()?.into_temp_path())
+}
+
+/// Sorts the lines and writes them to a temporary file.
+fn spill(sort: &Sort, lines: &[String]) -> Result<TempPath> {
+    let sorted = sort.sort_lines(lines.iter().map(String::as_str).collect())?;
+
+    write_run(|writer| {
+        for line in sorted {
+            writeln!(writer, "{line}")?;
+        }
+        Ok(())
+    })
+}
+
+/// Merges groups of consecutive runs until at most [`MERGE_FAN_IN`] are left,
+/// so lines with equal columns keep the order of the runs.
+fn reduce_runs(sort: &Sort, mut paths: Vec<TempPath>) -> Result<Vec<TempPath>> {
+    while paths.len() > MERGE_FAN_IN {
+        paths = paths
+            .chunks(MERGE_FAN_IN)
+            .map(|group| {
+                let runs = group.iter().map(Run::open).collect::<Result<Vec<_>>>()?;
+                write_run(|writer| merge_runs(sort, runs, |line| Ok(writeln!(writer, "{line}")?)))
+            })
+            .collect::<Result<_>>()?;
+    }
+
+    Ok(paths)
+}
+
+/// Sorts lines that may not fit in memory: sorted runs of about `buffer_size` bytes
+/// are spilled to temporary files and then merged.
+///
+/// The output is the same as of [`Sort::sort_contents`] of all the inputs, a line each,
+/// runs are merged in the input order, so lines with equal columns keep their order.
+pub(crate) fn sort_external<'input>(
+    sort: &Sort,
+    inputs: impl IntoIterator<Item = Box<dyn BufRead + 'input>>,
+    buffer_size: usize,
+    output: impl Write,
+) -> Result<()> {
+    let mut paths = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_size = 0;
+
+    for line in inputs.into_iter().flat_map(BufRead::lines) {
+        let line = line?;
+        chunk_size += line.len() + LINE_OVERHEAD;
+        chunk.push(line);
+
+        if chunk_size >= buffer_size {
+            paths.push(spill(sort, &chunk)?);
+            chunk.clear();
+            chunk_size = 0;
+        }
+    }
+    if !chunk.is_empty() {
+        paths.push(spill(sort, &chunk)?);
+    }
+    drop(chunk);
+
+    let paths = reduce_runs(sort, paths)?;
+    let runs = paths.iter().map(Run::open).collect::<Result<Vec<_>>>()?;
+
+    write_merged(sort, runs, output)
+}
+
+/// Merges inputs that are already sorted, for `-m`, without sorting them again.
+pub(crate) fn merge<'input>(
+    sort: &Sort,
+    inputs: impl IntoIterator<Item = Box<dyn BufRead + 'input>>,
+    output: impl Write,
+) -> Result<()> {
+    let runs = inputs.into_iter().map(Run::new).collect();
+
+    write_merged(sort, runs, output)
+}
+
+/// Merges the runs, passing the lines to `merged` in order.
+fn merge_runs(
+    sort: &Sort,
+    mut runs: Vec<Run<'_>>,
+    mut merged: impl FnMut(String) -> Result<()>,
+) -> Result<()> {
+    let mut heads = BinaryHeap::new();
+    for (run, lines) in runs.iter_mut().enumerate() {
+        if let Some(line) = lines.next_line(sort)? {
+            heads.push(Reverse(Head { sort, line, run }));
+        }
+    }
+
+    while let Some(Reverse(head)) = heads.pop() {
+        if let Some(line) = runs[head.run].next_line(sort)? {
+            heads.push(Reverse(Head {
+                sort,
+                line,
+                run: head.run,
+            }));
+        }
+        merged(head.line)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the lines of the runs in order, a line each.
+fn write_merged(sort: &Sort, runs: Vec<Run<'_>>, mut output: impl Write) -> Result<()> {
+    // lines with equal keys are merged one after another, so only the first line
+    // of the current group is remembered for `-u`, and the groups for `--count-duplicates`
+    let mut group: Option<String> = None;
+    let mut counts: Vec<(usize, String)> = Vec::new();
+
+    merge_runs(sort, runs, |mut line| {
+        line.truncate(sort.trim(&line).len());
+
+        if sort.count_duplicates {
+            match counts.last_mut() {
+                Some((count, first)) if sort.compare_lines(first, &line)?.is_eq() => *count += 1,
+                _ => counts.push((1, line)),
+            }
+            return Ok(());
+        }
+
+        if sort.unique {
+            match &group {
+                Some(first) if sort.compare_lines(first, &line)?.is_eq() => return Ok(()),
+                _ => group = Some(line.clone()),
+            }
+        }
+
+        writeln!(output, "{line}")?;
+        Ok(())
+    })?;
+
+    if sort.count_duplicates {
+        sort::write_counts(counts, &mut output)?;
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs<'input>(parts: &[&'input str]) -> Vec<Box<dyn BufRead + 'input>> {
+        parts
+            .iter()
+            .map(|part| Box::new(part.as_bytes()) as Box<dyn BufRead>)
+            .collect()
+    }
+
+    fn contents() -> String {
+        let words = ["pear", "apple", "fig", "kiwi", "plum"];
+        let months = ["jan", "Feb", "mar", "April", "may", "jun"];
+        let suffixes = ["k", "M", "B"];
+
+        // the first lines are repeated for `-u`
+        (0..200_i64)
+            .chain(0..50)
+            .map(|index| {
+                let number = (index * 37) % 23 - 11;
+                format!(
+                    "{},{number},{}{},{}{}",
+                    words[(index % 5) as usize],
+                    number % 4,
+                    suffixes[(index % 3) as usize],
+                    months[(index % 6) as usize],
+                    if index % 7 == 0 { "  " } else { "" },
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_same_as_in_memory() {
+        let contents = contents();
+
+        for keys in [
+            &["1,1"][..],
+            &["2,2n"],
+            &["3,3h"],
+            &["4,4M"],
+            &["4,4Mr", "1.2,1.3f"],
+        ] {
+            for (reverse, unique, stable, ignore_trailing_spaces) in [
+                (false, false, false, false),
+                (true, false, false, false),
+                (false, true, false, false),
+                (false, false, true, false),
+                (true, true, true, true),
+            ] {
+                let sort = Sort::builder()
+                    .keys(
+                        keys.iter()
+                            .map(|key| key.parse().expect("valid key"))
+                            .collect(),
+                    )
+                    .ignore_trailing_spaces(ignore_trailing_spaces)
+                    .reverse(reverse)
+                    .separator(",".to_owned())
+                    .stable(stable)
+                    .unique(unique)
+                    .build();
+
+                let sorted = |contents: &str| {
+                    sort.sort_contents(contents)
+                        .expect("no Result::Err in tests")
+                        .iter()
+                        .map(|line| format!("{line}\n"))
+                        .collect::<String>()
+                };
+                let expected = sorted(&contents);
+                let (first, second) = contents.split_at(contents.len() / 2);
+                let (first, second) = match second.split_once('\n') {
+                    Some((end, second)) => (&contents[..first.len() + end.len()], second),
+                    None => (contents.as_str(), ""),
+                };
+
+                for buffer_size in [1, 100, 1000, usize::MAX] {
+                    let mut output = Vec::new();
+                    sort_external(&sort, inputs(&[first, second]), buffer_size, &mut output)
+                        .expect("no Result::Err in tests");
+
+                    assert_eq!(
+                        String::from_utf8(output).expect("output is UTF-8"),
+                        expected,
+                        "keys {keys:?}, buffer size {buffer_size}"
+                    );
+                }
+
+                let mut output = Vec::new();
+                merge(
+                    &sort,
+                    inputs(&[&sorted(first), &sorted(second)]),
+                    &mut output,
+                )
+                .expect("no Result::Err in tests");
+                assert_eq!(
+                    String::from_utf8(output).expect("output is UTF-8"),
+                    expected,
+                    "keys {keys:?}, merged"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_several_merge_passes() {
+        let contents = (0..1000_i64)
+            .map(|index| format!("{},{index}", (index * 7919) % 101 - 50))
+            .collect::<Vec<_>>()
+            .join("\n");
+        // a line per run needs three passes: 1000 runs, then 63, then 4
+        assert!(contents.lines().count() > MERGE_FAN_IN * MERGE_FAN_IN);
+
+        let sort = Sort::builder()
+            .keys(vec!["1,1n".parse().expect("valid key")])
+            .ignore_trailing_spaces(false)
+            .reverse(false)
+            .separator(",".to_owned())
+            .stable(true)
+            .unique(false)
+            .build();
+
+        let expected = sort
+            .sort_contents(&contents)
+            .expect("no Result::Err in tests")
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+
+        let mut output = Vec::new();
+        sort_external(&sort, inputs(&[&contents]), 1, &mut output)
+            .expect("no Result::Err in tests");
+        assert_eq!(
+            String::from_utf8(output).expect("output is UTF-8"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_count_duplicates() {
+        let contents = contents();
+
+        for keys in [&["1,1"][..], &["4,4M"], &["3,3h", "1.1,1.1"]] {
+            let sort = Sort::builder()
+                .keys(
+                    keys.iter()
+                        .map(|key| key.parse().expect("valid key"))
+                        .collect(),
+                )
+                .count_duplicates(true)
+                .ignore_trailing_spaces(false)
+                .reverse(false)
+                .separator(",".to_owned())
+                .stable(false)
+                .unique(false)
+                .build();
+
+            let mut expected = Vec::new();
+            let counts = sort
+                .count_contents(&contents)
+                .expect("no Result::Err in tests");
+            sort::write_counts(counts, &mut expected).expect("writing to a vector");
+
+            for buffer_size in [1, 1000] {
+                let mut output = Vec::new();
+                sort_external(&sort, inputs(&[&contents]), buffer_size, &mut output)
+                    .expect("no Result::Err in tests");
+
+                assert_eq!(output, expected, "keys {keys:?}, buffer size {buffer_size}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_line() {
+        let sort = Sort::builder()
+            .keys(vec!["1n".parse().expect("valid key")])
+            .ignore_trailing_spaces(false)
+            .reverse(false)
+            .separator(",".to_owned())
+            .stable(false)
+            .unique(false)
+            .build();
+
+        let result = sort_external(&sort, inputs(&["1\n2\nthree\n4"]), 1, std::io::sink());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{fmt, ops::Range, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// How the text of a key is compared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyKind {
+    /// Compare strings byte by byte
+    #[default]
+    String,
+    /// Compare integer numbers, `n`
+    Numeric,
+    /// Compare numbers with the `k`, `M` and `B` suffixes, `h`
+    HumanNumeric,
+    /// Compare month names, `M`
+    Month,
+    /// Compare floating point numbers, `g`
+    GeneralNumeric,
+    /// Compare version strings, `V`
+    Version,
+    /// Compare hashes of keys, so equal keys are still grouped, `R`
+    Random,
+}
+
+/// Options of a key that change how it's compared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Modifiers {
+    pub(crate) kind: KeyKind,
+    /// Reverse the order of the key, `r`
+    pub(crate) reverse: bool,
+    /// Skip blanks at the start of the key, `b`
+    pub(crate) ignore_leading_blanks: bool,
+    /// Compare lowercase letters as uppercase, `f`
+    pub(crate) fold_case: bool,
+    /// Compare only letters, digits and blanks, `d`
+    pub(crate) dictionary_order: bool,
+}
+
+impl Modifiers {
+    /// Applies an option letter, returns `false` if it's unknown.
+    fn apply(&mut self, option: char) -> Result<bool> {
+        let kind = match option {
+            'n' => KeyKind::Numeric,
+            'h' => KeyKind::HumanNumeric,
+            'M' => KeyKind::Month,
+            'g' => KeyKind::GeneralNumeric,
+            'V' => KeyKind::Version,
+            'R' => KeyKind::Random,
+            'r' => {
+                self.reverse = true;
+                return Ok(true);
+            }
+            'b' => {
+                self.ignore_leading_blanks = true;
+                return Ok(true);
+            }
+            'f' => {
+                self.fold_case = true;
+                return Ok(true);
+            }
+            'd' => {
+                self.dictionary_order = true;
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+
+        if self.kind != KeyKind::String && self.kind != kind {
+            bail!(
+                "options '{}' and '{option}' are incompatible",
+                self.kind_option()
+            );
+        }
+        self.kind = kind;
+
+        Ok(true)
+    }
+
+    fn kind_option(self) -> &'static str {
+        match self.kind {
+            KeyKind::String => "",
+            KeyKind::Numeric => "n",
+            KeyKind::HumanNumeric => "h",
+            KeyKind::Month => "M",
+            KeyKind::GeneralNumeric => "g",
+            KeyKind::Version => "V",
+            KeyKind::Random => "R",
+        }
+    }
+}
+
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.ignore_leading_blanks, "b"),
+            (self.dictionary_order, "d"),
+            (self.fold_case, "f"),
+            (true, self.kind_option()),
+            (self.reverse, "r"),
+        ];
+
+        for (enabled, option) in flags {
+            if enabled {
+                f.write_str(option)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Position of a key boundary: a field and a character in it, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KeyPosition {
+    pub(crate) field: usize,
+    /// Character in the field, `0` at the end position means the end of the field
+    pub(crate) char: usize,
+}
+
+/// A sort key in the GNU `sort -k` syntax: `F[.C][OPTS][,F[.C][OPTS]]`.
+///
+/// The key spans from the start position to the end position, or to the end of the line,
+/// including the separators between the fields.
+/// A key without options inherits the global ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KeySpec {
+    pub(crate) start: KeyPosition,
+    pub(crate) end: Option<KeyPosition>,
+    modifiers: Option<Modifiers>,
+}
+
+impl KeySpec {
+    /// The key spanning the whole line.
+    pub(crate) fn whole_line() -> Self {
+        Self {
+            start: KeyPosition { field: 1, char: 1 },
+            end: None,
+            modifiers: None,
+        }
+    }
+
+    /// Uses the global options if the key has none of its own.
+    #[must_use]
+    pub(crate) fn inherit(mut self, global: Modifiers) -> Self {
+        self.modifiers.get_or_insert(global);
+        self
+    }
+
+    pub(crate) fn modifiers(&self) -> Modifiers {
+        self.modifiers.unwrap_or_default()
+    }
+
+    /// Extracts the text of the key, `None` if the line doesn't have the start field.
+    pub(crate) fn extract<'line>(&self, line: &'line str, separator: &str) -> Option<&'line str> {
+        let fields = field_ranges(line, separator);
+
+        let start_field = fields.get(self.start.field - 1)?;
+        let mut start = start_field.start;
+        let mut start_field_end = start_field.end;
+        if self.modifiers().ignore_leading_blanks {
+            // like GNU sort, blanks are skipped up to the end of the line, also across
+            // empty fields when the separator is a blank
+            start = skip_blanks(line, start, line.len());
+            start_field_end = start_field_end.max(start);
+        }
+        start = advance_chars(line, start, start_field_end, self.start.char - 1);
+
+        let end = match self.end {
+            None => line.len(),
+            Some(end) => match fields.get(end.field - 1) {
+                None => line.len(),
+                Some(field) if end.char == 0 => field.end,
+                Some(field) => advance_chars(line, field.start, field.end, end.char),
+            },
+        };
+
+        Some(line.get(start..end).unwrap_or_default())
+    }
+}
+
+/// Byte ranges of the fields of the line.
+fn field_ranges(line: &str, separator: &str) -> Vec<Range<usize>> {
+    let mut start = 0;
+
+    line.split(separator)
+        .map(|field| {
+            let range = start..start + field.len();
+            start = range.end + separator.len();
+            range
+        })
+        .collect()
+}
+
+fn skip_blanks(line: &str, start: usize, end: usize) -> usize {
+    let blanks = line[start..end].len() - line[start..end].trim_start_matches([' ', '\t']).len();
+    start + blanks
+}
+
+/// Moves the byte offset forward by `chars` characters, but not past `end`.
+fn advance_chars(line: &str, start: usize, end: usize, chars: usize) -> usize {
+    line[start..end]
+        .char_indices()
+        .nth(chars)
+        .map_or(end, |(offset, _)| start + offset)
+}
+
+/// Parses `F[.C][OPTS]`, the character defaults to `default_char`.
+fn parse_position(
+    position: &str,
+    default_char: usize,
+    modifiers: &mut Modifiers,
+    has_modifiers: &mut bool,
+) -> Result<KeyPosition> {
+    let options_start = position
+        .find(|char: char| !char.is_ascii_digit() && char != '.')
+        .unwrap_or(position.len());
+    let (numbers, options) = position.split_at(options_start);
+
+    let (field, char) = match numbers.split_once('.') {
+        Some((field, char)) => (field, Some(char)),
+        None => (numbers, None),
+    };
+    let field = field
+        .parse::<usize>()
+        .ok()
+        .filter(|&field| field > 0)
+        .ok_or_else(|| anyhow!("field number must be a positive integer: \"{field}\""))?;
+    let char = match char {
+        Some(char) => char
+            .parse::<usize>()
+            .with_context(|| format!("invalid character number: \"{char}\""))?,
+        None => default_char,
+    };
+
+    for option in options.chars() {
+        if !modifiers.apply(option)? {
+            bail!("unknown option '{option}'");
+        }
+        *has_modifiers = true;
+    }
+
+    Ok(KeyPosition { field, char })
+}
+
+impl FromStr for KeySpec {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut modifiers = Modifiers::default();
+        let mut has_modifiers = false;
+
+        let (start, end) = match spec.split_once(',') {
+            Some((start, end)) => (start, Some(end)),
+            None => (spec, None),
+        };
+
+        let start = parse_position(start, 1, &mut modifiers, &mut has_modifiers)
+            .with_context(|| format!("invalid key \"{spec}\""))?;
+        if start.char == 0 {
+            bail!("invalid key \"{spec}\": character number must be positive");
+        }
+        let end = end
+            .map(|end| parse_position(end, 0, &mut modifiers, &mut has_modifiers))
+            .transpose()
+            .with_context(|| format!("invalid key \"{spec}\""))?;
+
+        Ok(Self {
+            start,
+            end,
+            modifiers: has_modifiers.then_some(modifiers),
+        })
+    }
+}
+
+impl fmt::Display for KeySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_position = |f: &mut fmt::Formatter<'_>, position: KeyPosition, default| {
+            write!(f, "{}", position.field)?;
+            if position.char != default {
+                write!(f, ".{}", position.char)?;
+            }
+            Ok(())
+        };
+
+        write_position(f, self.start, 1)?;
+        if let Some(end) = self.end {
+            f.write_str(",")?;
+            write_position(f, end, 0)?;
+        }
+
+        write!(f, "{}", self.modifiers())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    use super::*;
+
+    fn key(spec: &str) -> KeySpec {
+        spec.parse().expect("valid key")
+    }
+
+    #[test]
+    fn test_parse() {
+        expect![[r"
+            KeySpec {
+                start: KeyPosition {
+                    field: 3,
+                    char: 2,
+                },
+                end: Some(
+                    KeyPosition {
+                        field: 3,
+                        char: 5,
+                    },
+                ),
+                modifiers: Some(
+                    Modifiers {
+                        kind: Numeric,
+                        reverse: true,
+                        ignore_leading_blanks: true,
+                        fold_case: false,
+                        dictionary_order: false,
+                    },
+                ),
+            }"]]
+        .assert_eq(&format!("{:#?}", key("3.2bn,3.5r")));
+
+        let displayed = ["2,2n", "1,1r", "3.2,3.5", "1", "2b,3"]
+            .map(|spec| key(spec).to_string())
+            .join(" ");
+        expect!["2,2n 1,1r 3.2,3.5 1 2,3b"].assert_eq(&displayed);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let errors = ["0", "1.0", "a", "1,2x", "2,2nM", "1z"]
+            .map(|spec| format!("{:#}", spec.parse::<KeySpec>().expect_err("invalid key")))
+            .join("\n");
+
+        expect![[r#"
+            invalid key "0": field number must be a positive integer: "0"
+            invalid key "1.0": character number must be positive
+            invalid key "a": field number must be a positive integer: ""
+            invalid key "1,2x": unknown option 'x'
+            invalid key "2,2nM": options 'n' and 'M' are incompatible
+            invalid key "1z": unknown option 'z'"#]]
+        .assert_eq(&errors);
+    }
+
+    #[test]
+    fn test_inherit() {
+        let global = Modifiers {
+            kind: KeyKind::Numeric,
+            ..Modifiers::default()
+        };
+
+        assert_eq!(key("2").inherit(global).modifiers(), global);
+        assert_eq!(key("2r").inherit(global).modifiers().kind, KeyKind::String);
+    }
+
+    #[test]
+    fn test_extract() {
+        let line = "one,  two,three,four";
+        let extracted = [
+            "1", "2", "2,2", "2b,2", "3.2,3.4", "2,3", "3.2,4.0", "4.9", "2.2b,2", "5",
+        ]
+        .map(|spec| format!("{spec} => {:?}", key(spec).extract(line, ",")))
+        .join("\n");
+
+        expect![[r#"
+            1 => Some("one,  two,three,four")
+            2 => Some("  two,three,four")
+            2,2 => Some("  two")
+            2b,2 => Some("two")
+            3.2,3.4 => Some("hre")
+            2,3 => Some("  two,three")
+            3.2,4.0 => Some("hree,four")
+            4.9 => Some("")
+            2.2b,2 => Some("wo")
+            5 => None"#]]
+        .assert_eq(&extracted);
+
+        assert_eq!(key("1b").extract("  two three", " "), Some("two three"));
+        // the key ends at the end of the first field, before the blanks
+        assert_eq!(key("1b,1").extract("  two three", " "), Some(""));
+    }
+}
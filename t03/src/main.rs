@@ -1,23 +1,45 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use camino::Utf8PathBuf;
 use clap::Parser;
-use std::process::ExitCode;
+use key::{KeyKind, KeySpec, Modifiers};
+use ordering::Collation;
+use output::Output;
+use std::{
+    collections::hash_map::RandomState,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::{self, BufRead, BufReader, Read, Write},
+    process::ExitCode,
+};
 
+mod external;
+mod key;
 mod months;
+mod ordering;
+mod output;
 mod sort;
 
-/// Utility for sorting strings in file
+/// Utility for sorting lines of files
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Parser)]
+#[command(disable_help_flag = true)]
 struct Args {
-    /// Path to the input file for sorting
-    input_path: Utf8PathBuf,
+    /// Files to sort together, `-` is the standard input, which is also read without files
+    files: Vec<Utf8PathBuf>,
 
-    /// Path to the output sorted file
-    output_path: Utf8PathBuf,
+    /// Write the sorted lines to FILE instead of the standard output, FILE may be an input
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output_path: Option<Utf8PathBuf>,
 
-    /// Column number to sort by
-    sort_column: usize,
+    /// Merge files that are already sorted, without sorting them
+    #[arg(short = 'm', long = "merge")]
+    merge: bool,
+
+    /// Sort by a key `F[.C][OPTS][,F[.C][OPTS]]`: from field F, character C to the end field,
+    /// or to the end of the line. OPTS are b, n, M, h, g, V, R, r, f and d like the global flags.
+    /// Can be repeated, ties are broken by the next key and then by the whole line
+    #[arg(short = 'k', long = "key", value_name = "KEYDEF")]
+    keys: Vec<KeySpec>,
 
     #[clap(flatten)]
     sort_flags: SortFlags,
@@ -26,20 +48,90 @@ struct Args {
     #[arg(short = 'r')]
     reverse: bool,
 
-    /// Do not output dublicates
+    /// Fold lowercase letters to uppercase when comparing strings
+    #[arg(short = 'f', long = "ignore-case")]
+    fold_case: bool,
+
+    /// Compare only letters, digits and blanks of strings
+    #[arg(short = 'd', long = "dictionary-order")]
+    dictionary_order: bool,
+
+    /// Seed the random order of `-R` with the contents of FILE, for a reproducible order
+    #[arg(long = "random-source", value_name = "FILE")]
+    random_source: Option<Utf8PathBuf>,
+
+    /// How strings are compared: byte by byte, with the Unicode Collation Algorithm,
+    /// or with runs of digits compared as numbers
+    #[arg(long, value_enum, default_value_t)]
+    collation: Collation,
+
+    /// Keep lines with equal keys in the input order, instead of comparing whole lines.
+    /// `-s` used to sort by numbers with suffixes, which is now `-h`
+    #[arg(short = 's', long = "stable")]
+    stable: bool,
+
+    /// Output only the first line of each group of lines with equal keys
     #[arg(short = 'u')]
     unique: bool,
 
-    /// Ignore trailing spaces
-    #[arg(short = 'b')]
+    /// Output the first line of each group of lines with equal keys, after the number of lines
+    /// in the group like `uniq -c` does, the largest groups first
+    #[arg(long = "count-duplicates")]
+    count_duplicates: bool,
+
+    /// Skip leading blanks of the keys, like the `b` key option
+    #[arg(short = 'b', long = "ignore-leading-blanks")]
+    ignore_leading_blanks: bool,
+
+    /// Ignore trailing spaces, was `-b`
+    #[arg(long = "ignore-trailing-spaces")]
     ignore_trailing_spaces: bool,
 
-    /// Check if the data is already sorted
-    #[arg(short = 'c')]
-    check_sorted: bool,
+    /// Check that the input is sorted, report the first line out of order and exit with 1
+    #[arg(short = 'c', long = "check", conflicts_with = "check_quietly")]
+    check: bool,
+
+    /// Like -c, but don't report the line out of order
+    #[arg(short = 'C')]
+    check_quietly: bool,
 
     #[arg(long = "sep", default_value = " ")]
     separator: String,
+
+    /// Print help
+    #[arg(long, action = clap::ArgAction::Help)]
+    help: Option<bool>,
+
+    /// Sort in memory up to SIZE, then spill sorted runs to temporary files and merge them.
+    /// SIZE is in kibibytes, or has a suffix: b, K, M, G
+    #[arg(short = 'S', long = "buffer-size", value_name = "SIZE", value_parser = parse_buffer_size)]
+    buffer_size: Option<usize>,
+}
+
+/// Parses a buffer size like GNU sort does, in kibibytes unless a suffix is given.
+fn parse_buffer_size(size: &str) -> Result<usize> {
+    let (number, unit) = match size.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let unit = match suffix {
+                'b' => 1,
+                'k' | 'K' => 1 << 10,
+                'm' | 'M' => 1 << 20,
+                'g' | 'G' => 1 << 30,
+                _ => return Err(anyhow!("unknown size suffix '{suffix}'")),
+            };
+            (&size[..index], unit)
+        }
+        _ => (size, 1 << 10),
+    };
+
+    let number = number
+        .parse::<usize>()
+        .with_context(|| format!("invalid buffer size: \"{size}\""))?;
+
+    number
+        .checked_mul(unit)
+        .filter(|&size| size > 0)
+        .ok_or_else(|| anyhow!("invalid buffer size: \"{size}\""))
 }
 
 #[derive(Parser, Debug)]
@@ -53,51 +145,188 @@ struct SortFlags {
     #[arg(short = 'M')]
     sort_month: bool,
 
-    /// Sort by numeric value taking into account suffixes
-    #[arg(short = 's')]
+    /// Sort by numeric value taking into account suffixes, was `-s`
+    #[arg(short = 'h', long = "human-numeric-sort")]
     sort_numbers_with_suffixes: bool,
+
+    /// Sort by floating point numbers, NaN first, then -inf, numbers and inf
+    #[arg(short = 'g', long = "general-numeric-sort")]
+    sort_general_numbers: bool,
+
+    /// Sort by versions, so 1.9 goes before 1.10
+    #[arg(short = 'V', long = "version-sort")]
+    sort_versions: bool,
+
+    /// Shuffle lines, keeping equal keys together
+    #[arg(short = 'R', long = "random-sort")]
+    sort_random: bool,
+}
+
+impl SortFlags {
+    fn kind(&self) -> KeyKind {
+        if self.sort_numbers {
+            KeyKind::Numeric
+        } else if self.sort_numbers_with_suffixes {
+            KeyKind::HumanNumeric
+        } else if self.sort_month {
+            KeyKind::Month
+        } else if self.sort_general_numbers {
+            KeyKind::GeneralNumeric
+        } else if self.sort_versions {
+            KeyKind::Version
+        } else if self.sort_random {
+            KeyKind::Random
+        } else {
+            KeyKind::String
+        }
+    }
+}
+
+impl Args {
+    /// Options for the keys that don't have their own.
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            kind: self.sort_flags.kind(),
+            reverse: self.reverse,
+            ignore_leading_blanks: self.ignore_leading_blanks,
+            fold_case: self.fold_case,
+            dictionary_order: self.dictionary_order,
+        }
+    }
+
+    /// Inputs in the order of the files, the standard input if there are none.
+    fn inputs(&self) -> Result<Vec<Box<dyn BufRead>>> {
+        if self.files.is_empty() {
+            return Ok(vec![Box::new(io::stdin().lock())]);
+        }
+
+        // the standard input is read once, a repeated `-` is empty like at the end of input
+        let mut stdin = Some(io::stdin().lock());
+
+        self.files
+            .iter()
+            .map(|path| -> Result<Box<dyn BufRead>> {
+                if path == "-" {
+                    return Ok(match stdin.take() {
+                        Some(stdin) => Box::new(stdin),
+                        None => Box::new(io::empty()),
+                    });
+                }
+                let file = File::open(path).with_context(|| format!("cannot read {path}"))?;
+                Ok(Box::new(BufReader::new(file)))
+            })
+            .collect()
+    }
+
+    /// Seed for `-R`, from the random source or different on every run.
+    fn random_seed(&self) -> Result<u64> {
+        let Some(path) = &self.random_source else {
+            return Ok(RandomState::new().build_hasher().finish());
+        };
+
+        let source =
+            std::fs::read(path).with_context(|| format!("cannot read random source {path}"))?;
+
+        Ok(ordering::random_hash(0, &source))
+    }
 }
 
-fn run(args: Args) -> Result<()> {
-    let contents = std::fs::read_to_string(args.input_path)?;
+/// Exit status when `-c` finds a line out of order, errors exit with 2 like GNU sort.
+const DISORDER: u8 = 1;
+
+fn run(args: Args) -> Result<ExitCode> {
+    let check = args.check || args.check_quietly;
+    if let (true, [_, extra, ..]) = (check, args.files.as_slice()) {
+        bail!("extra operand \"{extra}\" not allowed with -c");
+    }
+
+    let modifiers = args.modifiers();
+    let random_seed = args.random_seed()?;
+    let inputs = args.inputs()?;
+    let keys = if args.keys.is_empty() {
+        vec![KeySpec::whole_line().inherit(modifiers)]
+    } else {
+        args.keys
+            .into_iter()
+            .map(|key| key.inherit(modifiers))
+            .collect()
+    };
 
     let sort = sort::Sort::builder()
-        .sort_column(args.sort_column)
-        .by_numbers(args.sort_flags.sort_numbers)
-        .by_numbers_with_suffixes(args.sort_flags.sort_numbers_with_suffixes)
-        .by_month(args.sort_flags.sort_month)
+        .keys(keys)
         .reverse(args.reverse)
+        .stable(args.stable)
+        .random_seed(random_seed)
+        .collation(args.collation)
         .unique(args.unique)
+        .count_duplicates(args.count_duplicates)
         .ignore_trailing_spaces(args.ignore_trailing_spaces)
-        .check_sorted(args.check_sorted)
         .separator(args.separator)
         .build();
 
-    if let Some(sorted) = sort.check_is_sorted(&contents)? {
-        if sorted {
-            println!("Sorted");
-        } else {
-            println!("Not sorted");
+    if check {
+        let input = inputs
+            .into_iter()
+            .next()
+            .expect("standard input without files");
+        let Some(disorder) = sort.find_disorder(input)? else {
+            return Ok(ExitCode::SUCCESS);
+        };
+
+        if args.check {
+            let name = args.files.first().map_or("-", |path| path.as_str());
+            eprintln!(
+                "sort: {name}:{}: disorder: {}",
+                disorder.number, disorder.line
+            );
         }
 
-        return Ok(());
+        return Ok(ExitCode::from(DISORDER));
     }
 
-    let sorted = sort.sort_contents(&contents)?;
-    let sorted_contents = sorted.join("\n");
+    // inputs are read before the output file is replaced, so it may be one of them
+    let mut output = Output::new(args.output_path.as_deref())?;
 
-    std::fs::write(args.output_path, sorted_contents)?;
+    if args.merge {
+        external::merge(&sort, inputs, &mut output)?;
+    } else if let Some(buffer_size) = args.buffer_size {
+        external::sort_external(&sort, inputs, buffer_size, &mut output)?;
+    } else {
+        let contents = read_inputs(inputs)?;
+        if args.count_duplicates {
+            sort::write_counts(sort.count_contents(&contents)?, &mut output)?;
+        } else {
+            for line in sort.sort_contents(&contents)? {
+                writeln!(output, "{line}")?;
+            }
+        }
+    }
 
-    Ok(())
+    output.finish()?;
+    eprintln!("{}", sort.description());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reads the inputs one after another, a missing newline at the end of an input is added.
+fn read_inputs(inputs: Vec<Box<dyn BufRead>>) -> Result<String> {
+    let mut contents = String::new();
+
+    for mut input in inputs {
+        input.read_to_string(&mut contents)?;
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+    }
+
+    Ok(contents)
 }
 
 fn main() -> ExitCode {
     let args = Args::parse();
 
-    if let Err(err) = run(args) {
+    run(args).unwrap_or_else(|err| {
         eprintln!("{err:?}");
-        return ExitCode::FAILURE;
-    }
-
-    ExitCode::SUCCESS
+        ExitCode::from(2)
+    })
 }
@@ -0,0 +1,240 @@
+use std::{cell::RefCell, cmp::Ordering};
+
+use feruca::Collator;
+
+/// A floating point number for `-g`: NaN is smaller than any number, including `-inf`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GeneralNumber(pub(crate) f64);
+
+impl Ord for GeneralNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => self.0.partial_cmp(&other.0).expect("numbers are not NaN"),
+        }
+    }
+}
+
+impl PartialOrd for GeneralNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for GeneralNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for GeneralNumber {}
+
+/// A version string for `-V`, ordered like Debian versions: `1.9 < 1.10`, `1.0~rc1 < 1.0`.
+///
+/// Non-digit parts are compared character by character with letters before other characters
+/// and `~` before everything, even the end of the part; digit parts are compared as numbers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Version<'line>(pub(crate) &'line str);
+
+/// Weight of a character of a non-digit part, `None` is the end of the part.
+fn version_weight(char: Option<u8>) -> i32 {
+    match char {
+        None => 0,
+        Some(b'~') => -1,
+        Some(char) if char.is_ascii_alphabetic() => i32::from(char),
+        Some(char) => i32::from(char) + 256,
+    }
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&digit| digit == b'0').count();
+    &digits[zeros..]
+}
+
+impl Ord for Version<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (mut a, mut b) = (self.0.as_bytes(), other.0.as_bytes());
+
+        while !a.is_empty() || !b.is_empty() {
+            let non_digit = |part: &[u8]| {
+                part.iter()
+                    .take_while(|char| !char.is_ascii_digit())
+                    .count()
+            };
+            let (a_len, b_len) = (non_digit(a), non_digit(b));
+
+            for index in 0..a_len.max(b_len) {
+                let a_weight = version_weight(a[..a_len].get(index).copied());
+                let b_weight = version_weight(b[..b_len].get(index).copied());
+                if a_weight != b_weight {
+                    return a_weight.cmp(&b_weight);
+                }
+            }
+            (a, b) = (&a[a_len..], &b[b_len..]);
+
+            let digits = |part: &[u8]| part.iter().take_while(|char| char.is_ascii_digit()).count();
+            let (a_len, b_len) = (digits(a), digits(b));
+            let (a_number, b_number) = (trim_zeros(&a[..a_len]), trim_zeros(&b[..b_len]));
+
+            let cmp = a_number
+                .len()
+                .cmp(&b_number.len())
+                .then_with(|| a_number.cmp(b_number));
+            if cmp.is_ne() {
+                return cmp;
+            }
+            (a, b) = (&a[a_len..], &b[b_len..]);
+        }
+
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version<'_> {}
+
+/// How strings are compared, for string keys and the comparison of whole lines.
+///
+/// Strings that are equal under a collation but differ in bytes are ordered by bytes,
+/// so only identical strings are equal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Collation {
+    /// Byte by byte, so `Z` goes before `a` and `Éclair` after `zebra`
+    #[default]
+    Bytes,
+    /// The Unicode Collation Algorithm with the root locale, so `Éclair` goes before `zebra`
+    Unicode,
+    /// Runs of digits are compared as numbers, so `file2` goes before `file10`
+    Natural,
+}
+
+thread_local! {
+    static COLLATOR: RefCell<Collator> = RefCell::new(Collator::default());
+}
+
+impl Collation {
+    pub(crate) fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Self::Bytes => a.cmp(b),
+            Self::Unicode => COLLATOR.with_borrow_mut(|collator| collator.collate(a, b)),
+            Self::Natural => natural_cmp(a.as_bytes(), b.as_bytes()).then_with(|| a.cmp(b)),
+        }
+    }
+}
+
+/// Compares runs of digits as numbers and everything else byte by byte.
+fn natural_cmp(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    loop {
+        let (Some(&a_first), Some(&b_first)) = (a.first(), b.first()) else {
+            return a.len().cmp(&b.len());
+        };
+
+        if !a_first.is_ascii_digit() || !b_first.is_ascii_digit() {
+            if a_first != b_first {
+                return a_first.cmp(&b_first);
+            }
+            (a, b) = (&a[1..], &b[1..]);
+            continue;
+        }
+
+        let digits = |part: &[u8]| part.iter().take_while(|char| char.is_ascii_digit()).count();
+        let (a_len, b_len) = (digits(a), digits(b));
+        let (a_number, b_number) = (trim_zeros(&a[..a_len]), trim_zeros(&b[..b_len]));
+
+        let cmp = a_number
+            .len()
+            .cmp(&b_number.len())
+            .then_with(|| a_number.cmp(b_number));
+        if cmp.is_ne() {
+            return cmp;
+        }
+        (a, b) = (&a[a_len..], &b[b_len..]);
+    }
+}
+
+/// Hashes the text with the seed for `-R`, the hash is the same on every platform and run,
+/// so a `--random-source` reproduces the order.
+pub(crate) fn random_hash(seed: u64, text: &[u8]) -> u64 {
+    // FNV-1a, then the SplitMix64 finalizer to mix the bits
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64 ^ seed;
+    for &byte in text {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+    use itertools::Itertools;
+
+    use super::*;
+
+    #[test]
+    fn test_general_numbers() {
+        let mut numbers = ["1e3", "-inf", "2.5", "NaN", "inf", "-1E-2", "0", "-0"]
+            .map(|number| (GeneralNumber(number.parse().expect("valid float")), number));
+        numbers.sort();
+
+        expect!["NaN -inf -1E-2 -0 0 2.5 1e3 inf"]
+            .assert_eq(&numbers.iter().map(|(_, number)| number).join(" "));
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut versions = [
+            "1.10", "1.9", "1.0~rc1", "1.0", "1.0a", "1.0.1", "01.2", "1.2", "a", "",
+        ]
+        .map(Version);
+        versions.sort();
+
+        expect![[r#"[Version(""), Version("1.0~rc1"), Version("1.0"), Version("1.0a"), Version("1.0.1"), Version("01.2"), Version("1.2"), Version("1.9"), Version("1.10"), Version("a")]"#]]
+            .assert_eq(&format!("{versions:?}"));
+    }
+
+    #[test]
+    fn test_collations() {
+        let words = [
+            "zebra", "Éclair", "file10", "eclair", "file2", "Zebra", "file02", "file1a",
+        ];
+        let collated = [Collation::Bytes, Collation::Unicode, Collation::Natural]
+            .map(|collation| {
+                let mut words = words;
+                words.sort_by(|a, b| collation.compare(a, b));
+                format!("{collation:?}: {}", words.join(" "))
+            })
+            .join("\n");
+
+        expect![[r#"
+            Bytes: Zebra eclair file02 file10 file1a file2 zebra Éclair
+            Unicode: eclair Éclair file02 file10 file1a file2 zebra Zebra
+            Natural: Zebra eclair file1a file02 file2 file10 zebra Éclair"#]]
+        .assert_eq(&collated);
+    }
+
+    #[test]
+    fn test_random_hash() {
+        assert_eq!(random_hash(1, b"apple"), random_hash(1, b"apple"));
+        assert_ne!(random_hash(1, b"apple"), random_hash(2, b"apple"));
+        assert_ne!(random_hash(1, b"apple"), random_hash(1, b"apples"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    fs,
+    io::{self, BufWriter, StdoutLock, Write},
+};
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use tempfile::NamedTempFile;
+
+/// Where the sorted lines go: the standard output or a file given with `-o`.
+///
+/// The file is written to a temporary file next to it and replaced only when sorting succeeds,
+/// so it may also be one of the inputs.
+pub(crate) enum Output<'path> {
+    Stdout(BufWriter<StdoutLock<'static>>),
+    File {
+        path: &'path Utf8Path,
+        writer: BufWriter<NamedTempFile>,
+    },
+}
+
+impl<'path> Output<'path> {
+    pub(crate) fn new(path: Option<&'path Utf8Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::Stdout(BufWriter::new(io::stdout().lock())));
+        };
+
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_str().is_empty() => parent,
+            _ => Utf8Path::new("."),
+        };
+        let file = NamedTempFile::new_in(directory)
+            .with_context(|| format!("cannot create a temporary file in {directory}"))?;
+
+        Ok(Self::File {
+            path,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Flushes the lines, and replaces the output file with them, keeping its permissions.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            Self::Stdout(mut writer) => writer.flush()?,
+            Self::File { path, writer } => {
+                let file = writer.into_inner()?;
+                // the temporary file is only readable by the owner
+                match fs::metadata(path) {
+                    Ok(metadata) => file.as_file().set_permissions(metadata.permissions())?,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err).with_context(|| format!("cannot read {path}")),
+                }
+
+                file.persist(path)
+                    .with_context(|| format!("cannot write {path}"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(writer) => writer.write(buf),
+            Self::File { writer, .. } => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(writer) => writer.flush(),
+            Self::File { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().expect("temporary directory");
+        let path =
+            camino::Utf8PathBuf::try_from(directory.path().join("sorted.txt")).expect("UTF-8 path");
+        fs::write(&path, "b\na\n").expect("writing the input");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).expect("setting the mode");
+
+        let mut output = Output::new(Some(&path)).expect("creating the output");
+        output.write_all(b"a\nb\n").expect("writing the output");
+        output.finish().expect("replacing the output");
+
+        let mode = fs::metadata(&path)
+            .expect("output exists")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o644);
+        assert_eq!(
+            fs::read_to_string(&path).expect("reading the output"),
+            "a\nb\n"
+        );
+    }
+}
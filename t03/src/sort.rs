@@ -1,382 +1,397 @@
-use crate::months::Months;
+use crate::{
+    key::{KeyKind, KeySpec},
+    months::Months,
+    ordering::{self, Collation, GeneralNumber, Version},
+};
 use anyhow::{anyhow, Context, Result};
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
 
 /// Sorting options
 #[allow(clippy::struct_excessive_bools)]
 #[derive(bon::Builder)]
 pub(crate) struct Sort {
-    /// Column number to sort by
-    pub(crate) sort_column: usize,
+    /// Keys to compare lines by, ties are broken by the next key and then by the whole line
+    pub(crate) keys: Vec<KeySpec>,
 
-    /// Sort numbers
-    pub(crate) by_numbers: bool,
+    /// Keep lines with equal keys in the input order instead of comparing whole lines
+    pub(crate) stable: bool,
 
-    /// Sort by numeric value taking into account suffixes
-    pub(crate) by_numbers_with_suffixes: bool,
+    /// Seed of the hashes of random keys
+    #[builder(default)]
+    pub(crate) random_seed: u64,
 
-    /// Sort by month
-    pub(crate) by_month: bool,
+    /// How string keys and whole lines are compared
+    #[builder(default)]
+    pub(crate) collation: Collation,
 
-    /// Sort in reverse order
+    /// Sort in reverse order, also applies to the comparison of whole lines
     pub(crate) reverse: bool,
 
-    /// Do not output dublicates
+    /// Output only the first line of each run of lines with equal keys
     pub(crate) unique: bool,
 
+    /// Output the first line of each run of lines with equal keys with the number of lines in it
+    #[builder(default)]
+    pub(crate) count_duplicates: bool,
+
     /// Ignore trailing spaces
     pub(crate) ignore_trailing_spaces: bool,
 
-    /// Check if the data is already sorted
-    pub(crate) check_sorted: bool,
-
     pub(crate) separator: String,
 }
 
+/// Value of a key extracted from a line, the variant depends on the kind of the key.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Comparable<'line> {
+    Str(Cow<'line, str>),
+    Number(i64),
+    Month(Months),
+    GeneralNumber(GeneralNumber),
+    Version(Version<'line>),
+    Random(u64),
+}
+
+/// The first line that is out of order, found by `-c`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Disorder {
+    /// Number of the line, counted from 1
+    pub(crate) number: usize,
+    pub(crate) line: String,
+}
+
 impl Sort {
     pub(crate) fn sort_contents<'line>(&self, contents: &'line str) -> Result<Vec<&'line str>> {
-        let mut sortable = contents.lines().collect::<Vec<_>>();
-        let mut message = String::new();
+        let sortable = contents.lines().map(|line| self.trim(line)).collect();
+        let sorted = self.sort_lines(sortable)?;
 
-        if self.unique {
-            let mut set = std::collections::HashSet::new();
-            sortable.retain(|line| set.insert(line.to_owned()));
-            message.push_str(", ignoring duplicate rows");
+        if !self.unique {
+            return Ok(sorted);
         }
 
-        let sorted = match (
-            self.by_numbers,
-            self.by_numbers_with_suffixes,
-            self.by_month,
-        ) {
-            (true, false, false) => {
-                message = format!(
-                    "Sorted by numbers from column {}{message}.",
-                    self.sort_column
-                );
-                self.sort_by_numbers(sortable)
-            }
-            (false, true, false) => {
-                message = format!(
-                    "Sorted by numbers with suffixes from column {}{message}.",
-                    self.sort_column
-                );
-                self.sort_by_numbers_with_suffixes(sortable)
-            }
-            (false, false, true) => {
-                message = format!(
-                    "Sorted by months from column {}{message}.",
-                    self.sort_column
-                );
-                self.sort_by_months(sortable)
-            }
-            (false, false, false) => {
-                message = format!(
-                    "Sorted by strings from column {}{message}.",
-                    self.sort_column
-                );
-                self.sort_by_str(sortable)
-            }
-            // unreacheble, because there is `multiple = false` above the `SortFlags` structure
-            _ => unreachable!(),
-        }?;
+        let groups = self.count_groups(sorted)?;
+        Ok(groups.into_iter().map(|(_, line)| line).collect())
+    }
 
-        println!("{message}");
+    /// Sorts the lines and counts the lines with equal keys, for `--count-duplicates`.
+    pub(crate) fn count_contents<'line>(
+        &self,
+        contents: &'line str,
+    ) -> Result<Vec<(usize, &'line str)>> {
+        let sortable = contents.lines().map(|line| self.trim(line)).collect();
 
-        Ok(sorted)
+        self.count_groups(self.sort_lines(sortable)?)
     }
 
-    pub(crate) fn check_is_sorted(&self, contents: &str) -> Result<Option<bool>> {
-        if !self.check_sorted {
-            return Ok(None);
+    /// Counts runs of sorted lines with equal keys, keeping the first line of each run.
+    pub(crate) fn count_groups<L: AsRef<str>>(
+        &self,
+        sorted: impl IntoIterator<Item = L>,
+    ) -> Result<Vec<(usize, L)>> {
+        let mut groups: Vec<(usize, L)> = Vec::new();
+
+        for line in sorted {
+            if let Some((count, first)) = groups.last_mut() {
+                if self.compare_lines(first.as_ref(), line.as_ref())?.is_eq() {
+                    *count += 1;
+                    continue;
+                }
+            }
+            groups.push((1, line));
         }
 
-        let mut sortable = contents.lines().collect::<Vec<_>>();
+        Ok(groups)
+    }
 
-        if self.unique {
-            let mut set = std::collections::HashSet::new();
-            sortable.retain(|line| set.insert(line.to_owned()));
-        }
+    /// Whether lines with equal keys compare equal, instead of being compared as whole lines.
+    fn compares_keys_only(&self) -> bool {
+        self.stable || self.unique || self.count_duplicates
+    }
 
-        let is_sorted = match (
-            self.by_numbers,
-            self.by_numbers_with_suffixes,
-            self.by_month,
-        ) {
-            (true, false, false) => {
-                let comparable_and_line = self.prepare_comparable_numbers_column(sortable)?;
-                comparable_and_line
-                    .into_iter()
-                    .tuple_windows()
-                    .all(|((a_cmp, _), (b_cmp, _))| a_cmp <= b_cmp)
-            }
-            (false, true, false) => {
-                let comparable_and_line = self.prepare_comparable_suffixes_column(sortable)?;
-                comparable_and_line
-                    .into_iter()
-                    .tuple_windows()
-                    .all(|((a_cmp, _), (b_cmp, _))| a_cmp <= b_cmp)
-            }
-            (false, false, true) => {
-                let comparable_and_line = self.prepare_comparable_months_column(sortable)?;
-                comparable_and_line
-                    .into_iter()
-                    .tuple_windows()
-                    .all(|((a_cmp, _), (b_cmp, _))| a_cmp <= b_cmp)
-            }
-            (false, false, false) => {
-                let comparable_and_line = self.prepare_comparable_strs_column(sortable)?;
-                comparable_and_line
-                    .into_iter()
-                    .tuple_windows()
-                    .all(|((a_cmp, _), (b_cmp, _))| a_cmp <= b_cmp)
-            }
-            _ => unreachable!(),
+    /// Describes how the lines are sorted, for the message after sorting.
+    pub(crate) fn description(&self) -> String {
+        let keys = match self.keys.as_slice() {
+            [] => "whole lines".to_owned(),
+            [key] => format!("key {key}"),
+            keys => format!("keys {}", keys.iter().join(" ")),
+        };
+        let unique = if self.count_duplicates {
+            ", counting lines with equal keys"
+        } else if self.unique {
+            ", keeping the first line of equal keys"
+        } else {
+            ""
         };
 
-        Ok(Some(is_sorted))
+        format!("Sorted by {keys}{unique}.")
     }
 
-    pub(crate) fn sort<Comparable: Ord, Line>(
-        &self,
-        mut sortable: Vec<(Comparable, Line)>,
-    ) -> Vec<Line> {
-        sortable.sort_unstable_by(|(a, _), (b, _)| {
-            let cmp = a.cmp(b);
-
-            if self.reverse {
-                cmp.reverse()
-            } else {
-                cmp
+    /// Finds the first line that goes before the previous one,
+    /// or repeats it with `-u`, `None` if the input is sorted.
+    pub(crate) fn find_disorder(&self, input: impl BufRead) -> Result<Option<Disorder>> {
+        let mut previous: Option<String> = None;
+
+        for (index, line) in input.lines().enumerate() {
+            let line = line?;
+
+            if let Some(previous) = &previous {
+                let in_order = match self.compare_lines(previous, &line)? {
+                    Ordering::Less => true,
+                    Ordering::Equal => !self.unique,
+                    Ordering::Greater => false,
+                };
+                if !in_order {
+                    return Ok(Some(Disorder {
+                        number: index + 1,
+                        line,
+                    }));
+                }
             }
-        });
 
-        sortable
-            .into_iter()
-            .map(|(_, line)| line)
-            .collect::<Vec<_>>()
+            previous = Some(line);
+        }
+
+        Ok(None)
     }
 
-    // Sorts lines by the column with numbers when the `-n` flag is specified.
-    pub(crate) fn sort_by_numbers<'line>(
-        &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<&'line str>> {
-        let sortable = self.prepare_comparable_numbers_column(sortable)?;
+    /// Sorts the lines by the keys, lines that compare equal keep their order.
+    pub(crate) fn sort_lines<'line>(&self, sortable: Vec<&'line str>) -> Result<Vec<&'line str>> {
+        let mut prepared = sortable
+            .into_iter()
+            .map(|line| self.prepare_comparable_line(line))
+            .collect::<Result<Vec<_>>>()?;
 
-        let sorted = self.sort(sortable);
+        prepared.sort_by(|a, b| self.compare_prepared(a, b));
 
-        Ok(sorted)
+        Ok(prepared.into_iter().map(|(_, line)| line).collect())
     }
 
-    // Sorts lines by the column with numbers with suffixes when `-s` flag is specified.
-    pub(crate) fn sort_by_numbers_with_suffixes<'line>(
-        &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<&'line str>> {
-        let sortable = self.prepare_comparable_suffixes_column(sortable)?;
-
-        let sorted = self.sort(sortable);
+    /// Compares two lines the same way [`Sort::sort_lines`] orders them.
+    pub(crate) fn compare_lines(&self, a: &str, b: &str) -> Result<Ordering> {
+        let a = self.prepare_comparable_line(a)?;
+        let b = self.prepare_comparable_line(b)?;
 
-        Ok(sorted)
+        Ok(self.compare_prepared(&a, &b))
     }
 
-    // Sorts lines by the column with months when `-M` flag is specified.
-    pub(crate) fn sort_by_months<'line>(
+    fn compare_prepared(
         &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<&'line str>> {
-        let sortable = self.prepare_comparable_months_column(sortable)?;
-
-        let sorted = self.sort(sortable);
-
-        Ok(sorted)
-    }
+        (a_keys, a_line): &(Vec<Comparable<'_>>, &str),
+        (b_keys, b_line): &(Vec<Comparable<'_>>, &str),
+    ) -> Ordering {
+        let by_keys = self
+            .keys
+            .iter()
+            .zip(a_keys.iter().zip(b_keys))
+            .map(|(key, (a, b))| {
+                let cmp = match (a, b) {
+                    (Comparable::Str(a), Comparable::Str(b)) => self.collation.compare(a, b),
+                    _ => a.cmp(b),
+                };
+
+                if key.modifiers().reverse {
+                    cmp.reverse()
+                } else {
+                    cmp
+                }
+            })
+            .find(|cmp| cmp.is_ne());
 
-    // Sorts lines by the column with strings when no sort flag is specified.
-    pub(crate) fn sort_by_str<'line>(&self, sortable: Vec<&'line str>) -> Result<Vec<&'line str>> {
-        let sortable = self.prepare_comparable_strs_column(sortable)?;
+        by_keys.unwrap_or_else(|| {
+            if self.compares_keys_only() {
+                return Ordering::Equal;
+            }
 
-        let sorted = self.sort(sortable);
+            let cmp = self.collation.compare(a_line, b_line);
 
-        Ok(sorted)
+            if self.reverse {
+                cmp.reverse()
+            } else {
+                cmp
+            }
+        })
     }
 
-    /// Prepares lines by extracting the value from them by column number
+    /// Prepares a line by extracting the values of all keys from it
     ///
     /// ### Example
     ///
     /// Args:
-    ///      - column with numbers: `2`
-    ///     - flag to sort numbers: `-n`
+    ///     - keys: `-k2,2n -k1,1`
+    ///     - separator: ` `
     ///
-    /// Sort lines:
-    /// ```
-    ///   apple 2
-    /// vanille 1
-    /// ```
     /// Input:
     /// ```
-    /// ["  apple 2", "vanille 1"]
+    /// "apple 2"
     /// ```
     ///
     /// Output :
     /// ```
-    /// [(2_i64, "  apple 2"), (1_i64, "vanille 1")].
+    /// ([Comparable::Number(2), Comparable::Str("apple")], "apple 2")
     /// ```
-    pub(crate) fn prepare_comparable_numbers_column<'line>(
+    pub(crate) fn prepare_comparable_line<'line>(
         &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<(i64, &'line str)>> {
-        let lines = sortable
-            .into_iter()
-            .map(|line| {
-                let (nth, line) = self.extract_nth_column_from_line(line)?;
-
-                let comparable_number = nth.trim().parse::<i64>().with_context(|| {
-                    format!(
-                        "Column {} doesn't contain only numbers: \"{nth}\"",
-                        self.sort_column
-                    )
-                })?;
+        line: &'line str,
+    ) -> Result<(Vec<Comparable<'line>>, &'line str)> {
+        let line = self.trim(line);
 
-                Ok((comparable_number, line))
+        let comparables = self
+            .keys
+            .iter()
+            .map(|key| {
+                let text = key
+                    .extract(line, &self.separator)
+                    .ok_or_else(|| anyhow!("cannot find the start of key {key} in line: {line}"))?;
+
+                match key.modifiers().kind {
+                    KeyKind::String => Ok(self.prepare_comparable_str(key, text)),
+                    KeyKind::Numeric => self.prepare_comparable_number(key, text),
+                    KeyKind::HumanNumeric => self.prepare_comparable_suffix(key, text),
+                    KeyKind::Month => self.prepare_comparable_month(text),
+                    KeyKind::GeneralNumeric => self.prepare_comparable_general_number(key, text),
+                    KeyKind::Version => Ok(self.prepare_comparable_version(text)),
+                    KeyKind::Random => Ok(self.prepare_comparable_random(key, text)),
+                }
             })
             .collect::<Result<Vec<_>>>()?;
 
-        Ok(lines)
+        Ok((comparables, line))
     }
 
-    /// Prepares lines by extracting the value from them by column number
+    /// Prepares the value of a key with numbers, `n`
     ///
     /// ### Example
     ///
-    /// Args:
-    ///      - column with numbers: `2`
-    ///     - flag to sort numbers: `-s`
+    /// Input: `" -2"`, output: `Comparable::Number(-2)`
+    pub(crate) fn prepare_comparable_number<'line>(
+        &self,
+        key: &KeySpec,
+        text: &str,
+    ) -> Result<Comparable<'line>> {
+        let number = text
+            .trim()
+            .parse::<i64>()
+            .with_context(|| format!("Key {key} doesn't contain only numbers: \"{text}\""))?;
+
+        Ok(Comparable::Number(number))
+    }
+
+    /// Prepares the value of a key with numbers taking into account suffixes, `h`
     ///
-    /// Sort lines:
-    /// ```
-    ///   apple 2
-    /// vanille 1
-    /// ```
-    /// Input:
-    /// ```
-    /// ["  apple 2k", "vanille -1M"]
-    /// ```
+    /// ### Example
     ///
-    /// Output :
-    /// ```
-    /// [(2000_i64, "  apple 2k"), (-1_000_000_i64, "vanille -1M")].
-    /// ```
-    pub(crate) fn prepare_comparable_suffixes_column<'line>(
+    /// Input: `"-1M"`, output: `Comparable::Number(-1_000_000)`
+    pub(crate) fn prepare_comparable_suffix<'line>(
         &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<(i64, &'line str)>> {
+        key: &KeySpec,
+        text: &str,
+    ) -> Result<Comparable<'line>> {
         let suffixes =
             HashMap::<char, i64>::from_iter([('k', 1_000), ('M', 1_000_000), ('B', 1_000_000_000)]);
 
-        let lines = sortable
-            .into_iter()
-            .map(|line| self.parse_suffix_number(line, &suffixes))
-            .collect::<Result<Vec<_>>>()?;
+        let number = self.parse_suffix_number(key, text, &suffixes)?;
 
-        Ok(lines)
+        Ok(Comparable::Number(number))
     }
 
-    /// Prepares lines by extracting the value from them by column number
+    /// Prepares the value of a key with month names, `M`
     ///
     /// ### Example
     ///
-    /// Args:
-    ///      - column with numbers: `2`
-    ///     - flag to sort numbers: `-M`
+    /// Input: `"August"`, output: `Comparable::Month(Months::August)`
+    pub(crate) fn prepare_comparable_month<'line>(&self, text: &str) -> Result<Comparable<'line>> {
+        let month = Months::try_from(text)?;
+
+        Ok(Comparable::Month(month))
+    }
+
+    /// Prepares the value of a key with floating point numbers, `g`
     ///
-    /// Sort lines:
-    /// ```
-    ///   apple jan
-    /// vanille August
-    /// ```
-    /// Input:
-    /// ```
-    /// ["  apple jan", "vanille August"]
-    /// ```
+    /// ### Example
     ///
-    /// Output :
-    /// ```
-    /// [(Months::January, "  apple jan"), (Months::August, "vanille August")].
-    /// ```
-    pub(crate) fn prepare_comparable_months_column<'line>(
+    /// Input: `"-1.5e3"`, output: `Comparable::GeneralNumber(GeneralNumber(-1500.0))`
+    pub(crate) fn prepare_comparable_general_number<'line>(
         &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<(Months, &'line str)>> {
-        let sortable = sortable
-            .into_iter()
-            .map(|line| {
-                let (str_month, line) = self.extract_nth_column_from_line(line)?;
-                let month = Months::try_from(str_month)?;
-                Ok((month, line))
-            })
-            .collect::<Result<Vec<_>>>()?;
-
-        Ok(sortable)
+        key: &KeySpec,
+        text: &str,
+    ) -> Result<Comparable<'line>> {
+        let number = text
+            .trim()
+            .parse::<f64>()
+            .with_context(|| format!("Key {key} doesn't contain a number: \"{text}\""))?;
+
+        Ok(Comparable::GeneralNumber(GeneralNumber(number)))
     }
 
-    /// Prepares lines by extracting the value from them by column number
+    /// Prepares the value of a key with versions, `V`
     ///
     /// ### Example
     ///
-    /// Args:
-    ///      - column with numbers: `1`
+    /// Input: `"v1.10"`, output: `Comparable::Version(Version("v1.10"))`
+    pub(crate) fn prepare_comparable_version<'line>(&self, text: &'line str) -> Comparable<'line> {
+        Comparable::Version(Version(text.trim()))
+    }
+
+    /// Prepares the value of a random key, the hash of its string value, `R`
     ///
-    /// Sort lines:
-    /// ```
-    ///   apple pie
-    /// vanille syrup
-    /// ```
-    /// Input:
-    /// ```
-    /// ["  apple pie", "vanille syrup"]
-    /// ```
+    /// ### Example
     ///
-    /// Output :
-    /// ```
-    /// [("apple", "  apple pie"), ("vanille", "vanille syrup")].
-    /// ```
-    pub(crate) fn prepare_comparable_strs_column<'line>(
+    /// Input: `"apple"`, output: `Comparable::Random(random_hash(seed, b"apple"))`
+    pub(crate) fn prepare_comparable_random<'line>(
         &self,
-        sortable: Vec<&'line str>,
-    ) -> Result<Vec<(&'line str, &'line str)>> {
-        let sortable = sortable
-            .into_iter()
-            .map(|line| self.extract_nth_column_from_line(line))
-            .collect::<Result<Vec<_>>>()?;
+        key: &KeySpec,
+        text: &str,
+    ) -> Comparable<'line> {
+        let text = Self::translate(key, text);
 
-        Ok(sortable)
+        Comparable::Random(ordering::random_hash(self.random_seed, text.as_bytes()))
     }
 
-    pub(crate) fn extract_nth_column_from_line<'line>(
+    /// Prepares the value of a string key, changed by `f` and `d`
+    ///
+    /// ### Example
+    ///
+    /// Input: `"apple"`, output: `Comparable::Str("apple")`
+    pub(crate) fn prepare_comparable_str<'line>(
         &self,
-        line: &'line str,
-    ) -> Result<(&'line str, &'line str)> {
-        let line = self.trim(line);
-        let nth = line
-            .split(&self.separator)
-            .nth(self.sort_column - 1)
-            .ok_or_else(|| anyhow!("cannot find specified column: {}", self.sort_column))?;
+        key: &KeySpec,
+        text: &'line str,
+    ) -> Comparable<'line> {
+        Comparable::Str(Self::translate(key, text))
+    }
 
-        Ok((nth, line))
+    /// Keeps only letters, digits and blanks with `d`, folds lowercase letters to uppercase with `f`
+    fn translate<'line>(key: &KeySpec, text: &'line str) -> Cow<'line, str> {
+        let modifiers = key.modifiers();
+        let mut text = Cow::Borrowed(text);
+
+        if modifiers.dictionary_order {
+            text = text
+                .chars()
+                .filter(|char| char.is_alphanumeric() || matches!(char, ' ' | '\t'))
+                .collect::<String>()
+                .into();
+        }
+        if modifiers.fold_case {
+            text = text.to_uppercase().into();
+        }
+
+        text
     }
 
-    pub(crate) fn parse_suffix_number<'line>(
+    /// Parses a number with an optional suffix
+    pub(crate) fn parse_suffix_number(
         &self,
-        line: &'line str,
+        key: &KeySpec,
+        text: &str,
         suffixes: &HashMap<char, i64>,
-    ) -> Result<(i64, &'line str)> {
-        let (nth, line) = self.extract_nth_column_from_line(line)?;
-
-        let mut chars = nth.trim().chars().peekable();
+    ) -> Result<i64> {
+        let mut chars = text.trim().chars().peekable();
         let mut number: i64 = 0;
         let mut sign = 1;
 
@@ -387,28 +402,21 @@ impl Sort {
 
         while let Some(char) = chars.peek() {
             if let Some(digit) = char.to_digit(10) {
-                number = number * 10 + digit as i64;
+                number = number * 10 + i64::from(digit);
                 chars.next().expect("next chars exists");
             } else {
                 break;
             }
         }
 
-        let suffix = chars.next().ok_or_else(|| {
-            anyhow!(
-                "suffix not found in column {} in line: {}",
-                self.sort_column,
-                line
-            )
-        })?;
-
-        let suffix = suffixes
-            .get(&suffix)
-            .ok_or_else(|| anyhow!("unknown suffix '{suffix}' in line: {line}"))?;
-
-        number *= suffix * sign;
+        let suffix = match chars.next() {
+            Some(suffix) => *suffixes
+                .get(&suffix)
+                .ok_or_else(|| anyhow!("unknown suffix '{suffix}' in key {key}: {text}"))?,
+            None => 1,
+        };
 
-        Ok((number, line))
+        Ok(number * suffix * sign)
     }
 
     pub(crate) fn trim<'line>(&self, line: &'line str) -> &'line str {
@@ -420,6 +428,21 @@ impl Sort {
     }
 }
 
+/// Writes the counted lines like `uniq -c`, the most frequent first,
+/// lines that are as frequent keep their order.
+pub(crate) fn write_counts<L: AsRef<str>>(
+    mut counts: Vec<(usize, L)>,
+    mut output: impl Write,
+) -> io::Result<()> {
+    counts.sort_by_key(|&(count, _)| Reverse(count));
+
+    for (count, line) in counts {
+        writeln!(output, "{count:>7} {}", line.as_ref())?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use expect_test::{expect, Expect};
@@ -457,6 +480,21 @@ mod tests {
         "
     }
 
+    fn sort(keys: &[&str]) -> Sort {
+        Sort::builder()
+            .keys(
+                keys.iter()
+                    .map(|key| key.parse().expect("valid key"))
+                    .collect(),
+            )
+            .ignore_trailing_spaces(false)
+            .reverse(false)
+            .separator(",".to_owned())
+            .stable(false)
+            .unique(false)
+            .build()
+    }
+
     fn assert_expected(actual: &[&str], expect: &Expect) {
         let actual = actual.join("\n");
         expect.assert_eq(&actual);
@@ -464,19 +502,7 @@ mod tests {
 
     #[test]
     fn test_sort_by_number() {
-        let sort = Sort::builder()
-            .by_month(false)
-            .by_numbers(true)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(false)
-            .ignore_trailing_spaces(false)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(2)
-            .unique(false)
-            .build();
-
-        let sorted = sort
+        let sorted = sort(&["2,2n"])
             .sort_contents(contents())
             .expect("no Result::Err in tests");
 
@@ -500,19 +526,7 @@ mod tests {
 
     #[test]
     fn test_sort_by_suffix() {
-        let sort = Sort::builder()
-            .by_month(false)
-            .by_numbers(false)
-            .by_numbers_with_suffixes(true)
-            .check_sorted(false)
-            .ignore_trailing_spaces(false)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(3)
-            .unique(false)
-            .build();
-
-        let sorted = sort
+        let sorted = sort(&["3,3h"])
             .sort_contents(contents())
             .expect("no Result::Err in tests");
 
@@ -536,19 +550,7 @@ mod tests {
 
     #[test]
     fn test_sort_by_month() {
-        let sort = Sort::builder()
-            .by_month(true)
-            .by_numbers(false)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(false)
-            .ignore_trailing_spaces(false)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(4)
-            .unique(false)
-            .build();
-
-        let sorted = sort
+        let sorted = sort(&["4,4M"])
             .sort_contents(contents())
             .expect("no Result::Err in tests");
 
@@ -572,19 +574,7 @@ mod tests {
 
     #[test]
     fn test_sort_by_str() {
-        let sort = Sort::builder()
-            .by_month(false)
-            .by_numbers(false)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(false)
-            .ignore_trailing_spaces(true)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(1)
-            .unique(false)
-            .build();
-
-        let sorted = sort
+        let sorted = sort(&["1,1"])
             .sort_contents(contents())
             .expect("no Result::Err in tests");
 
@@ -608,18 +598,6 @@ mod tests {
 
     #[test]
     fn test_sort_by_str_with_several_equals_letters() {
-        let sort = Sort::builder()
-            .by_month(false)
-            .by_numbers(false)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(false)
-            .ignore_trailing_spaces(false)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(2)
-            .unique(false)
-            .build();
-
         let contents = "\
         7,ab\n\
         1,aaaab\n\
@@ -632,7 +610,7 @@ mod tests {
         9,ac\n\
         ";
 
-        let sorted = sort
+        let sorted = sort(&["2,2"])
             .sort_contents(contents)
             .expect("no Result::Err in tests");
 
@@ -653,17 +631,10 @@ mod tests {
 
     #[test]
     fn test_sort_with_unique() {
-        let sort = Sort::builder()
-            .by_month(false)
-            .by_numbers(false)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(false)
-            .ignore_trailing_spaces(false)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(2)
-            .unique(true)
-            .build();
+        let sort = Sort {
+            unique: true,
+            ..sort(&["2,2"])
+        };
 
         let contents = "\
         0,a\n\
@@ -685,30 +656,216 @@ mod tests {
             &sorted,
             &expect![[r"
                 0,a
-                4,a
                 3,aa
                 2,aaa
                 1,aaaa"]],
         );
     }
 
+    #[test]
+    fn test_count_duplicates() {
+        let contents = "\
+        pear,1\n\
+        fig,2\n\
+        Pear,3\n\
+        apple,4\n\
+        fig,5\n\
+        pear,6\n\
+        ";
+        let sort = Sort {
+            count_duplicates: true,
+            ..sort(&["1,1f"])
+        };
+
+        let counts = sort
+            .count_contents(contents)
+            .expect("no Result::Err in tests");
+        let mut output = Vec::new();
+        write_counts(counts, &mut output).expect("writing to a vector");
+
+        assert_eq!(
+            String::from_utf8(output).expect("output is UTF-8"),
+            "      3 pear,1\n      2 fig,2\n      1 apple,4\n"
+        );
+    }
+
+    #[test]
+    fn test_sort_by_several_keys() {
+        let contents = "\
+        b,2,apple\n\
+        a,10,pear\n\
+        c,2,Apple\n\
+        a,2,fig\n\
+        b,10,kiwi\n\
+        a,2,fig\n\
+        ";
+
+        let sorted = sort(&["2,2n", "1,1r"])
+            .sort_contents(contents)
+            .expect("no Result::Err in tests");
+        assert_expected(
+            &sorted,
+            &expect![[r"
+                c,2,Apple
+                b,2,apple
+                a,2,fig
+                a,2,fig
+                b,10,kiwi
+                a,10,pear"]],
+        );
+
+        // characters 2 to 3 of the third field, case-insensitive, then the whole line breaks ties
+        let sorted = sort(&["3.2f,3.3"])
+            .sort_contents(contents)
+            .expect("no Result::Err in tests");
+        assert_expected(
+            &sorted,
+            &expect![[r"
+                a,10,pear
+                a,2,fig
+                a,2,fig
+                b,10,kiwi
+                b,2,apple
+                c,2,Apple"]],
+        );
+    }
+
+    #[test]
+    fn test_sort_modes() {
+        let contents = "\
+        1.10,2e1,pe-ar\n\
+        1.9,-inf,apple\n\
+        1.0~rc1,NaN,Pear!\n\
+        1.0,0.5,Apple\n\
+        ";
+        let sorted = |keys: &[&str]| {
+            sort(keys)
+                .sort_contents(contents)
+                .expect("no Result::Err in tests")
+                .iter()
+                .map(|line| line.split(',').next().expect("first field"))
+                .join(" ")
+        };
+
+        expect!["1.0 1.0~rc1 1.10 1.9"].assert_eq(&sorted(&["1,1"]));
+        expect!["1.0~rc1 1.0 1.9 1.10"].assert_eq(&sorted(&["1,1V"]));
+        expect!["1.0~rc1 1.9 1.0 1.10"].assert_eq(&sorted(&["2,2g"]));
+        expect!["1.0 1.9 1.10 1.0~rc1"].assert_eq(&sorted(&["3f"]));
+        expect!["1.0 1.9 1.0~rc1 1.10"].assert_eq(&sorted(&["3df"]));
+
+        let random = |seed| {
+            let sort = Sort {
+                random_seed: seed,
+                ..sort(&["3,3fR"])
+            };
+            sort.sort_contents(&contents.repeat(2))
+                .expect("no Result::Err in tests")
+                .join("\n")
+        };
+        assert_eq!(random(1), random(1));
+        // equal keys are next to each other
+        let shuffled = random(2);
+        let first = shuffled.lines().next().expect("first line");
+        assert_eq!(shuffled.lines().nth(1), Some(first));
+    }
+
+    #[test]
+    fn test_collation() {
+        let contents = "\
+        zebra,file10\n\
+        Éclair,file2\n\
+        apple,file1\n\
+        ";
+        let sorted = |keys: &[&str], collation| {
+            Sort {
+                collation,
+                ..sort(keys)
+            }
+            .sort_contents(contents)
+            .expect("no Result::Err in tests")
+            .join(" ")
+        };
+
+        expect!["apple,file1 zebra,file10 Éclair,file2"].assert_eq(&sorted(&[], Collation::Bytes));
+        expect!["apple,file1 Éclair,file2 zebra,file10"]
+            .assert_eq(&sorted(&[], Collation::Unicode));
+        expect!["apple,file1 Éclair,file2 zebra,file10"]
+            .assert_eq(&sorted(&["2,2"], Collation::Natural));
+        expect!["apple,file1 zebra,file10 Éclair,file2"]
+            .assert_eq(&sorted(&["2,2"], Collation::Bytes));
+    }
+
+    #[test]
+    fn test_stable() {
+        let contents = "\
+        b,1\n\
+        a,2\n\
+        c,1\n\
+        a,1\n\
+        ";
+
+        let sorted = sort(&["2,2n"])
+            .sort_contents(contents)
+            .expect("no Result::Err in tests");
+        assert_expected(
+            &sorted,
+            &expect![[r"
+            a,1
+            b,1
+            c,1
+            a,2"]],
+        );
+
+        let sorted = Sort {
+            stable: true,
+            ..sort(&["2,2n"])
+        }
+        .sort_contents(contents)
+        .expect("no Result::Err in tests");
+        assert_expected(
+            &sorted,
+            &expect![[r"
+            b,1
+            c,1
+            a,1
+            a,2"]],
+        );
+
+        // the reverse order also applies to the whole lines, but not to the input order
+        let reversed = |stable| {
+            Sort {
+                stable,
+                reverse: true,
+                ..sort(&["2,2nr"])
+            }
+            .sort_contents(contents)
+            .expect("no Result::Err in tests")
+            .join(" ")
+        };
+        assert_eq!(reversed(false), "a,2 c,1 b,1 a,1");
+        assert_eq!(reversed(true), "a,2 b,1 c,1 a,1");
+    }
+
     #[test]
     fn test_is_sorted_numbers() {
-        let sort = Sort::builder()
-            .by_month(false)
-            .by_numbers(true)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(true)
-            .ignore_trailing_spaces(false)
-            .reverse(false)
-            .separator(",".to_owned())
-            .sort_column(2)
-            .unique(false)
-            .build();
+        let sort = sort(&["2,2n"]);
 
         let sorted = sort
             .sort_contents(contents())
             .expect("no Result::Err in tests");
+        assert_eq!(
+            sort.find_disorder(contents().as_bytes())
+                .expect("valid numbers"),
+            Some(Disorder {
+                number: 3,
+                line: "h,  4,  4k, aug".to_owned()
+            })
+        );
+        assert_eq!(
+            sort.find_disorder(sorted.join("\n").as_bytes())
+                .expect("valid numbers"),
+            None
+        );
 
         assert_expected(
             &sorted,
@@ -728,23 +885,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_is_sorted_unique() {
+        let contents = "a,1\nb,1\nb,1\nc,2\n";
+
+        assert_eq!(
+            sort(&["2,2n"])
+                .find_disorder(contents.as_bytes())
+                .expect("valid numbers"),
+            None
+        );
+
+        let unique = Sort {
+            unique: true,
+            ..sort(&["2,2n"])
+        };
+        assert_eq!(
+            unique
+                .find_disorder(contents.as_bytes())
+                .expect("valid numbers"),
+            Some(Disorder {
+                number: 2,
+                line: "b,1".to_owned()
+            })
+        );
+    }
+
     #[test]
     fn test_is_sorted_reversed_months() {
-        let sort = Sort::builder()
-            .by_month(true)
-            .by_numbers(false)
-            .by_numbers_with_suffixes(false)
-            .check_sorted(true)
-            .ignore_trailing_spaces(false)
-            .reverse(true)
-            .separator(",".to_owned())
-            .sort_column(4)
-            .unique(false)
-            .build();
+        let sort = Sort {
+            reverse: true,
+            ..sort(&["4,4Mr"])
+        };
 
         let sorted = sort
             .sort_contents(contents())
             .expect("no Result::Err in tests");
+        assert_eq!(
+            sort.find_disorder(sorted.join("\n").as_bytes())
+                .expect("valid months"),
+            None
+        );
 
         assert_expected(
             &sorted,